@@ -6,6 +6,14 @@ use clap::builder::styling;
 use clap::{Arg, ArgAction, Command};
 use simplelog::*;
 
+use crate::{
+    filetypes::corpus::{CarvingMethod, FileNaming},
+    search::{EmbeddedPolicy, OversizePolicy},
+};
+
+// values given for some file types only, by extension
+pub type PerTypeValues = Vec<(String, usize)>;
+
 /// This structure holds the command line arguments.
 #[derive(Debug, Default)]
 pub struct CliOptions {
@@ -30,8 +38,29 @@ pub struct CliOptions {
     // maximum number of files to carve, after that, stops
     pub limit: Option<usize>,
 
-    // audit file where location of artefacts are found
-    pub audit: bool,
+    // maximum number of files to carve for some file types, given by their extension
+    pub type_limits: PerTypeValues,
+
+    // how carved files are named
+    pub naming: FileNaming,
+
+    // what to do with patterns found inside carved artefacts
+    pub embedded: EmbeddedPolicy,
+
+    // maximum size of the artefacts, for all file types
+    pub max_size: Option<usize>,
+
+    // maximum size of the artefacts for some file types, given by their extension
+    pub type_max_sizes: PerTypeValues,
+
+    // what to do with artefacts bigger than their maximum size
+    pub oversize: OversizePolicy,
+
+    // carving method used for all file types, instead of the one defined for each file type
+    pub carving_method: Option<CarvingMethod>,
+
+    // check the content of carved artefacts
+    pub validate: bool,
 }
 
 impl CliOptions {
@@ -82,6 +111,38 @@ impl CliOptions {
                     .value_parser(clap::value_parser!(usize))
                     .required(false),
             )
+            .arg(
+                Arg::new("maxsize")
+                    .short('M')
+                    .long("maxsize")
+                    .long_help(
+                        "Maximum length in bytes of the artefacts, for all file types. A comma-separated list of <EXT>=<SIZE> sets it per file type, e.g.: jpg=20000000,bmp=5000000. Variants are set by the extension of their file type, e.g.: wav for avi and webp, zip for docx and jar, mp4 for mov and heic",
+                    )
+                    .value_name("SIZE")
+                    .num_args(1)
+                    .value_delimiter(',')
+                    .required(false),
+            )
+            .arg(
+                Arg::new("oversize")
+                    .long("oversize")
+                    .long_help(
+                        "What to do with artefacts bigger than their maximum size: don't carve them (drop) or save their first bytes and flag them as truncated in the audit file (truncate)",
+                    )
+                    .value_name("POLICY")
+                    .value_parser(["drop", "truncate"])
+                    .default_value("drop"),
+            )
+            .arg(
+                Arg::new("method")
+                    .long("method")
+                    .long_help(
+                        "Carving method used for all file types: follow the file structure (strict), carve between a header and a footer (simple) or follow the file structure skipping damaged parts (fancy). If not set, each file type uses its own method",
+                    )
+                    .value_name("METHOD")
+                    .value_parser(["strict", "simple", "fancy"])
+                    .required(false),
+            )
             .arg(
                 Arg::new("nbthreads")
                     .short('n')
@@ -95,9 +156,12 @@ impl CliOptions {
                 Arg::new("limit")
                     .short('l')
                     .long("limit")
-                    .long_help("Stop carving after <LIMIT> files")
+                    .long_help(
+                        "Stop carving after <LIMIT> files for all threads. A comma-separated list of <EXT>=<LIMIT> caps the number of files per file type, e.g.: 1000,jpg=100,png=50. Variants are counted with their file type, e.g.: wav for avi and webp, zip for docx and jar, mp4 for mov and heic",
+                    )
                     .value_name("LIMIT")
-                    .value_parser(clap::value_parser!(usize))
+                    .num_args(1)
+                    .value_delimiter(',')
                     .required(false),
             )
             .arg(
@@ -123,17 +187,41 @@ impl CliOptions {
                     .long_help("Display progress bar"),
             )
             .arg(
-                Arg::new("audit")
-                    .long("audit")
-                    .short('a')
+                Arg::new("naming")
+                    .long("naming")
+                    .long_help(
+                        "How carved files are named: with a counter per file type (index) or with the offset of the artefact in the image (offset). Offset names are the same whatever the number of threads",
+                    )
+                    .value_name("NAMING")
+                    .value_parser(["index", "offset"])
+                    .default_value("index"),
+            )
+            .arg(
+                Arg::new("embedded")
+                    .long("embedded")
+                    .long_help(
+                        "What to do with signatures found inside an artefact already carved: don't carve them (skip) or carve them and record them as children of the artefact in the audit file (child)",
+                    )
+                    .value_name("POLICY")
+                    .value_parser(["skip", "child"])
+                    .default_value("skip"),
+            )
+            .arg(
+                Arg::new("validate")
+                    .long("validate")
                     .action(ArgAction::SetTrue)
-                    .long_help("Create the audit file"),
+                    .long_help(
+                        "Check the content of carved artefacts when possible, and record whether they're valid, corrupt or truncated in the audit file",
+                    ),
             )
             .arg(
                 Arg::new("ext")
                     .short('e')
                     .long("ext")
                     .help("Comma-separated list of extensions to carve")
+                    .long_help(
+                        "Comma-separated list of extensions to carve. Variants are carved with their file type, e.g.: wav for avi and webp, zip for docx and jar, mp4 for mov and heic",
+                    )
                     .num_args(1)
                     .value_delimiter(',')
                     .required(false),
@@ -145,16 +233,22 @@ impl CliOptions {
 
         // input file & layout file are mandatory. Try to canonicalize() at the same time.
         options.input_file = matches.get_one::<PathBuf>("input").unwrap().clone();
-        options.buffer_size = *matches.get_one::<usize>("buffer").unwrap_or_else(|| &4096);
+        options.buffer_size = *matches.get_one::<usize>("buffer").unwrap_or(&4096);
         options.min_size = *matches.get_one::<usize>("minsize").unwrap_or(&0);
         options.nb_threads = *matches.get_one::<usize>("nbthreads").unwrap_or(&1);
-        options.limit = matches.get_one::<usize>("limit").copied();
+
+        // limits and max sizes are either global or per file type
+        if let Some(limits) = matches.get_many::<String>("limit") {
+            (options.limit, options.type_limits) = per_type_values(limits)?;
+        }
+        if let Some(max_sizes) = matches.get_many::<String>("maxsize") {
+            (options.max_size, options.type_max_sizes) = per_type_values(max_sizes)?;
+        }
 
         options.ext_list = matches
             .get_many::<String>("ext")
-            .unwrap()
-            .map(|s| s.clone())
-            .collect();
+            .map(|exts| exts.cloned().collect())
+            .unwrap_or_default();
 
         // manage debugging
         if matches.contains_id("verbose") {
@@ -176,10 +270,59 @@ impl CliOptions {
         // set pb
         options.progress_bar = matches.get_flag("pb");
 
+        // set validation
+        options.validate = matches.get_flag("validate");
+
+        // set naming scheme
+        options.naming = match matches.get_one::<String>("naming").unwrap().as_str() {
+            "offset" => FileNaming::Offset,
+            _ => FileNaming::Index,
+        };
+
+        // set policy for embedded artefacts
+        options.embedded = match matches.get_one::<String>("embedded").unwrap().as_str() {
+            "child" => EmbeddedPolicy::Child,
+            _ => EmbeddedPolicy::Skip,
+        };
+
+        // set carving method
+        options.carving_method =
+            matches
+                .get_one::<String>("method")
+                .map(|method| match method.as_str() {
+                    "simple" => CarvingMethod::Simple,
+                    "fancy" => CarvingMethod::Fancy,
+                    _ => CarvingMethod::Strict,
+                });
+
+        // set policy for oversized artefacts
+        options.oversize = match matches.get_one::<String>("oversize").unwrap().as_str() {
+            "truncate" => OversizePolicy::Truncate,
+            _ => OversizePolicy::Drop,
+        };
+
         Ok(options)
     }
 }
 
+// split a list like 1000,jpg=100,png=50 into the value for all file types and the values
+// for each file type
+fn per_type_values<'a>(
+    values: impl Iterator<Item = &'a String>,
+) -> anyhow::Result<(Option<usize>, PerTypeValues)> {
+    let mut global = None;
+    let mut per_type = Vec::new();
+
+    for value in values {
+        match value.split_once('=') {
+            Some((ext, n)) => per_type.push((ext.to_string(), n.parse()?)),
+            None => global = Some(value.parse()?),
+        }
+    }
+
+    Ok((global, per_type))
+}
+
 // Initialize write logger: either create it or use it
 fn init_write_logger(logfile: &PathBuf, level: log::LevelFilter) -> anyhow::Result<()> {
     if level == log::LevelFilter::Off {
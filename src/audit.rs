@@ -1,17 +1,28 @@
 // audit related definitions
 
 use std::{
+    collections::BTreeMap,
     fs::File,
     io::{BufWriter, Write},
+    ops::Range,
     path::Path,
 };
 
-const AUDIT_FILE: &'static str = "audit.txt";
+use crate::validation::Validation;
+
+const AUDIT_FILE: &str = "audit.txt";
+
+// details found in the artefact content, written as key=value
+pub type Metadata = Vec<(&'static str, String)>;
 
 // this will hold all audit figures
 #[derive(Debug)]
 pub struct AuditFile {
     writer: BufWriter<File>,
+
+    // artefacts are kept until the end, to be written sorted by offset whatever the order
+    // threads found them
+    artefacts: Vec<AuditData>,
 }
 
 impl AuditFile {
@@ -21,6 +32,7 @@ impl AuditFile {
 
         Ok(Self {
             writer: BufWriter::new(f),
+            artefacts: Vec::new(),
         })
     }
 
@@ -37,27 +49,128 @@ impl AuditFile {
     }
 
     // add new data
-    pub fn add_artefact<'a>(&mut self, data: &AuditData<'a>) -> anyhow::Result<()> {
-        write!(
-            self.writer,
-            "{}: {}-{} (0x{:X?}-0x{:X?}) {}\n",
-            data.artefact,
-            data.offset_start,
-            data.offset_end,
-            data.offset_start,
-            data.offset_end,
-            data.length
-        )?;
+    pub fn add_artefact(&mut self, data: AuditData) {
+        self.artefacts.push(data);
+    }
+
+    // write all artefacts sorted by their starting offset
+    pub fn close(&mut self) -> anyhow::Result<()> {
+        self.artefacts.sort_by_key(|data| data.offset_start);
+
+        for data in &self.artefacts {
+            write!(
+                self.writer,
+                "{}: {}-{} (0x{:X?}-0x{:X?}) {}",
+                data.artefact,
+                data.offset_start,
+                data.offset_end,
+                data.offset_start,
+                data.offset_end,
+                data.length
+            )?;
+
+            // the outcome of checking the artefact content
+            if let Some(validation) = data.validation {
+                write!(self.writer, " status={}", validation)?;
+            }
+
+            // the artefact was cut at the maximum size of its file type
+            if data.truncated {
+                write!(self.writer, " truncated")?;
+            }
+
+            // damaged parts skipped when carving
+            if !data.gaps.is_empty() {
+                let gaps: Vec<_> = data
+                    .gaps
+                    .iter()
+                    .map(|gap| format!("0x{:X?}-0x{:X?}", gap.start, gap.end))
+                    .collect();
+                write!(self.writer, " damaged={}", gaps.join(","))?;
+            }
+
+            // files nested in the artefact
+            if !data.embedded.is_empty() {
+                let embedded: Vec<_> = data
+                    .embedded
+                    .iter()
+                    .map(|e| format!("0x{:X?}-0x{:X?}", e.start, e.end))
+                    .collect();
+                write!(self.writer, " embedded={}", embedded.join(","))?;
+            }
+
+            // details found when checking the content
+            for (key, value) in &data.metadata {
+                write!(self.writer, " {}={}", key, escape(value))?;
+            }
+
+            // embedded artefacts refer to the artefact containing them
+            if let Some(parent) = &data.parent {
+                write!(self.writer, " parent={}", parent)?;
+            }
+            writeln!(self.writer)?;
+        }
+
+        self.summary()?;
         self.writer.flush()?;
 
         Ok(())
     }
+
+    // number of artefacts taken by each camera model, found in the metadata
+    fn summary(&mut self) -> anyhow::Result<()> {
+        let mut cameras: BTreeMap<String, usize> = BTreeMap::new();
+
+        for data in &self.artefacts {
+            let get = |key| {
+                data.metadata
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, v)| v.as_str())
+            };
+
+            // the model often starts with the make already
+            let camera = match (get("make"), get("model")) {
+                (Some(make), Some(model)) if !model.starts_with(make) => {
+                    format!("{} {}", make, model)
+                }
+                (_, Some(model)) => model.to_string(),
+                (Some(make), None) => make.to_string(),
+                (None, None) => continue,
+            };
+            *cameras.entry(camera).or_default() += 1;
+        }
+
+        if !cameras.is_empty() {
+            writeln!(self.writer, "\ncamera models:")?;
+            for (camera, count) in cameras {
+                writeln!(self.writer, "{}: {}", escape(&camera), count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// values come from the artefacts and could be anything: they're quoted and escaped unless they
+// can't be mistaken for something else on the line
+fn escape(value: &str) -> String {
+    if value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '=' || c == '"')
+    {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
 }
 
 // interesting data to know for each artefact
-pub struct AuditData<'a> {
+#[derive(Debug)]
+pub struct AuditData {
     // artefact name
-    pub artefact: &'a str,
+    pub artefact: String,
 
     // starting offset in the image file
     pub offset_start: u64,
@@ -67,4 +180,37 @@ pub struct AuditData<'a> {
 
     // arteffact length
     pub length: u64,
+
+    // true if the artefact was cut at the maximum size of its file type
+    pub truncated: bool,
+
+    // the outcome of checking the artefact content, if done
+    pub validation: Option<Validation>,
+
+    // absolute offsets of the damaged parts skipped when carving
+    pub gaps: Vec<Range<u64>>,
+
+    // absolute offsets of the files nested in the artefact
+    pub embedded: Vec<Range<u64>>,
+
+    // details found in the artefact content
+    pub metadata: Metadata,
+
+    // name of the artefact containing this one, if any
+    pub parent: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_values() {
+        assert_eq!(escape("Canon"), "Canon");
+        assert_eq!(escape("Canon EOS 5D"), "\"Canon EOS 5D\"");
+        assert_eq!(escape(""), "\"\"");
+        assert_eq!(escape("EOS\nfake.jpg: 0-1"), "\"EOS\\nfake.jpg: 0-1\"");
+        assert_eq!(escape("a=b\tc"), "\"a=b\\tc\"");
+        assert_eq!(escape("say \"hi\""), "\"say \\\"hi\\\"\"");
+    }
 }
@@ -0,0 +1,99 @@
+// carves files between a header and a footer, without knowing anything of the file structure
+use log::debug;
+use memchr::memmem;
+
+use crate::filetypes::corpus::{FileType, Footer};
+
+use super::CarvingResult;
+
+pub fn carve_using_footer(mmap: &[u8], ft: &FileType) -> anyhow::Result<CarvingResult> {
+    let Some(footer) = &ft.footer else {
+        return Ok(CarvingResult::default());
+    };
+
+    // never look for the footer past the maximum size for this file type
+    let window = &mmap[..ft.max_size.min(mmap.len())];
+
+    // the footer is searched right after the header
+    let Some(magic) = ft.magics.iter().find(|magic| window.starts_with(magic)) else {
+        return Ok(CarvingResult::default());
+    };
+    let start = magic.len();
+
+    let end = match footer {
+        Footer::Include(footer) => {
+            memmem::find(&window[start..], footer).map(|pos| start + pos + footer.len())
+        }
+    };
+
+    match end {
+        Some(end) => {
+            // if the file we found is not big enough, do not consider it
+            if end < ft.min_size {
+                return Ok(CarvingResult::default());
+            }
+
+            Ok(CarvingResult::new(end as u64, end))
+        }
+
+        // no footer found: the artefact is bigger than the maximum size
+        None if window.len() == ft.max_size => {
+            debug!(
+                "file type {}: no footer found within the maximum size {}",
+                &ft.ext, ft.max_size
+            );
+            Ok(CarvingResult::truncated(window.len()))
+        }
+        None => Ok(CarvingResult::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, atomic::AtomicUsize};
+
+    use crate::filetypes::corpus::CarvingMethod;
+
+    use super::*;
+
+    fn file_type(footer: Footer) -> FileType {
+        FileType {
+            magics: vec![b"HDR".to_vec()],
+            ext: String::from("hdr"),
+            carving_func: carve_using_footer,
+            category: String::from("misc/hdr"),
+            min_size: 8,
+            max_size: 100,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Simple,
+            footer: Some(footer),
+            validation_func: None,
+            repeated_magic: false,
+        }
+    }
+
+    #[test]
+    fn footers() {
+        let mut image = b"HDR-data-END-more-HDR-last".to_vec();
+
+        // the footer is part of the artefact
+        let ft = file_type(Footer::Include(b"END".to_vec()));
+        let result = carve_using_footer(&image, &ft).unwrap();
+        assert_eq!(
+            (result.offset, result.length, result.truncated),
+            (12, 12, false)
+        );
+
+        // too small
+        assert_eq!(carve_using_footer(b"HDREND", &ft).unwrap().offset, 0);
+
+        // no footer before the end of the image, or within the maximum size
+        assert_eq!(carve_using_footer(&image[13..], &ft).unwrap().offset, 0);
+        image[9..12].copy_from_slice(b"end");
+        image.resize(200, b'-');
+        let result = carve_using_footer(&image, &ft).unwrap();
+        assert_eq!((result.length, result.truncated), (100, true));
+    }
+}
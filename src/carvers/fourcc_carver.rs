@@ -1,18 +1,80 @@
 // carves files ffollowing the FourCC pattern
-use std::{fmt::Debug, io::Cursor};
+use std::{fmt::Debug, io::Cursor, ops::Range};
 
 use log::{debug, trace};
 use std::io::ErrorKind;
 
 use crate::{
+    audit::Metadata,
     deserializer::Deserializer,
     filetypes::corpus::{CarvingMethod, FileType},
 };
 
-use super::CarvingResult;
+use super::{CarvingResult, Subtype};
 
 pub trait FourCCCarver {
     fn is_end(&self) -> bool; // test whether we met the final chunk
+
+    // some chunks are only found in a variant of the file type
+    fn subtype(&self) -> Option<Subtype> {
+        None
+    }
+
+    // other files nested in the chunk or referenced by it, relative to the artefact start. They
+    // are part of the artefact, which extends at least up to their end
+    fn embedded(&self) -> Vec<Range<u64>> {
+        Vec::new()
+    }
+
+    // true if the chunk is a frame of an animation
+    fn is_frame(&self) -> bool {
+        false
+    }
+
+    // details found in the chunk, reported in the audit
+    fn metadata(&self) -> Metadata {
+        Vec::new()
+    }
+
+    // offset in buffer of the next chunk which looks genuine, used to skip over damaged parts
+    fn resync(_buffer: &[u8]) -> Option<usize>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    // false if the file type has no end marker: the artefact then ends at the first data which
+    // is not a chunk
+    fn has_end() -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
+
+    // the chunk type, when some of them are required
+    fn fourcc(&self) -> Option<[u8; 4]> {
+        None
+    }
+
+    // the chunk types which must all be found for the file to be genuine, given its subtype
+    fn required(_subtype: Option<Subtype>) -> &'static [[u8; 4]]
+    where
+        Self: Sized,
+    {
+        &[]
+    }
+
+    // the maximum size of the file type, for chunks which have to look for their end
+    fn set_max_size(&mut self, _max_size: usize) {}
+
+    // keep what the previous chunk found, for chunks which depend on an earlier one
+    fn carry(&mut self, _previous: &Self)
+    where
+        Self: Sized,
+    {
+    }
 }
 
 pub fn fourcc_carver<T, U>(mmap: &[u8], ft: &FileType) -> anyhow::Result<CarvingResult>
@@ -24,13 +86,66 @@ where
     // read magic
     let mut header = T::default();
     let mut cursor = Cursor::new(mmap);
-    header.deserialize(&mut cursor)?;
+    if header.deserialize(&mut cursor).is_err() {
+        return Ok(CarvingResult::default());
+    }
+
+    // parts of the file skipped because they couldn't be parsed
+    let mut gaps = Vec::new();
+
+    // variant of the file type, if a chunk tells it
+    let mut subtype = None;
+
+    // nested files
+    let mut embedded = Vec::new();
+
+    // details found in the chunks
+    let mut metadata = Metadata::new();
+    let mut frames = 0usize;
+
+    // chunk types found
+    let mut fourccs = Vec::new();
+
+    // the last chunk carved
+    let mut previous: Option<U> = None;
+
+    // a chunk went past the maximum size
+    let mut oversize = false;
 
     loop {
         let mut chunk = U::default();
+        chunk.set_max_size(ft.max_size);
+        if let Some(previous) = &previous {
+            chunk.carry(previous);
+        }
+        let chunk_start = cursor.position() as usize;
 
         match chunk.deserialize(&mut cursor) {
+            // chunk lengths could be anything: stop walking chunks when we're past the maximum size
+            Ok(_) if cursor.position() > ft.max_size as u64 && ft.max_size <= mmap.len() => {
+                debug!(
+                    "file type {}: offset {} is over the maximum size {}",
+                    &ft.ext,
+                    cursor.position(),
+                    ft.max_size
+                );
+                // the artefact is only truncated if it's genuine: keep what tells it
+                subtype = subtype.or(chunk.subtype());
+                fourccs.extend(chunk.fourcc());
+                oversize = true;
+                break;
+            }
+            // the chunk goes past the end of the image
+            Ok(_) if cursor.position() > mmap.len() as u64 => {
+                return Ok(CarvingResult::default());
+            }
             Ok(_) => {
+                subtype = subtype.or(chunk.subtype());
+                embedded.extend(chunk.embedded());
+                metadata.extend(chunk.metadata());
+                frames += chunk.is_frame() as usize;
+                fourccs.extend(chunk.fourcc());
+
                 // did we find the end marker ?
                 if chunk.is_end() {
                     trace!(
@@ -41,19 +156,39 @@ where
                     );
                     break;
                 }
+                previous = Some(chunk);
+            }
+            // without an end marker, the artefact ends where chunks stop
+            Err(_) if !U::has_end() => {
+                cursor.set_position(chunk_start as u64);
+                break;
             }
             Err(e) => match e.kind() {
                 // not really an I/O error
                 // depending on the carving method, we stop here or continue
                 ErrorKind::InvalidData => match ft.carving_method {
                     // halt whenever a chunk is not recognized
-                    CarvingMethod::Strict => return Ok(CarvingResult::default()),
+                    CarvingMethod::Strict | CarvingMethod::Simple => {
+                        return Ok(CarvingResult::default());
+                    }
 
-                    // we continue till marker end or maximum length reached
-                    CarvingMethod::Simple => (),
+                    // look for the next chunk which seems valid and carry on from there
+                    CarvingMethod::Fancy => {
+                        let from = chunk_start + 1;
+                        let window = &mmap[..ft.max_size.min(mmap.len())];
+                        let Some(next) = window.get(from..).and_then(U::resync) else {
+                            return Ok(CarvingResult::default());
+                        };
 
-                    // we stop here
-                    CarvingMethod::Fancy => return Ok(CarvingResult::default()),
+                        debug!(
+                            "file type {}: skipping damaged data at offsets: 0x{:X?}-0x{:X?}",
+                            &ft.ext,
+                            chunk_start,
+                            from + next
+                        );
+                        gaps.push(chunk_start as u64..(from + next) as u64);
+                        cursor.set_position((from + next) as u64);
+                    }
                 },
                 // here, true I/O error
                 _ => {
@@ -67,7 +202,43 @@ where
         }
     }
 
-    // the cursor position is now the end of file
+    if !U::required(subtype)
+        .iter()
+        .all(|fourcc| fourccs.contains(fourcc))
+    {
+        debug!("file type {}: required chunks are missing", &ft.ext);
+        return Ok(CarvingResult::default());
+    }
+
+    if oversize {
+        return Ok(CarvingResult::truncated(ft.max_size));
+    }
+
+    // nested files come from untrusted offsets: ignore the ones past the maximum size
+    embedded.retain(|e| {
+        let kept = e.end <= ft.max_size as u64;
+        if !kept {
+            debug!(
+                "file type {}: nested file ending at {} is over the maximum size {}",
+                &ft.ext, e.end, ft.max_size
+            );
+        }
+        kept
+    });
+
+    // the cursor position is now the end of file, unless nested files are appended after the
+    // end marker
+    let end = embedded
+        .iter()
+        .map(|e| e.end)
+        .fold(cursor.position(), u64::max);
+
+    // appended files cut by the end of the image: keep what's complete
+    if end > mmap.len() as u64 {
+        embedded.retain(|e| e.end <= cursor.position());
+    } else {
+        cursor.set_position(end);
+    }
     let payload = &mmap[..cursor.position() as usize];
 
     // if the file we found is not bug enough, do not consider it
@@ -75,9 +246,14 @@ where
         return Ok(CarvingResult::default());
     }
 
-    // save file
-    let file_name = ft.save_file(payload)?;
-
-    // move offset, lock will be automatically released
-    Ok(CarvingResult::new(cursor.position(), &file_name, payload.len()))
+    // move offset, the file is saved by the caller
+    let mut result = CarvingResult::new(cursor.position(), payload.len());
+    result.gaps = gaps;
+    result.subtype = subtype;
+    result.embedded = embedded;
+    result.metadata = metadata;
+    if frames > 0 {
+        result.metadata.push(("frames", frames.to_string()));
+    }
+    Ok(result)
 }
@@ -1,5 +1,15 @@
 // all the carvers are located as modules here
-use std::fmt::Debug;
+use std::ops::Range;
+
+use crate::audit::Metadata;
+
+// a more precise file type, found when carving (e.g.: an animated PNG). Artefacts are saved
+// using its extension and category instead of the file type ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Subtype {
+    pub ext: &'static str,
+    pub category: &'static str,
+}
 
 // this is returned by the main seach function
 #[derive(Debug, Default)]
@@ -7,27 +17,57 @@ pub struct CarvingResult {
     // if a file is found, we need to move the offset when saving that file
     pub offset: u64,
 
-    // if a file is found, this is its file name
-    pub file_name: Option<String>,
-
     // payload length is the artefact length
     pub length: usize,
-/* 
+
+    // the artefact is bigger than the maximum size for its file type, and was cut at that size
+    pub truncated: bool,
+
+    // damaged parts of the artefact, skipped when carving, relative to the artefact start
+    pub gaps: Vec<Range<u64>>,
+
+    // the file type found, if more precise than the one of the magic
+    pub subtype: Option<Subtype>,
+
+    // files nested in the artefact (e.g.: JPEG thumbnails), relative to the artefact start
+    pub embedded: Vec<Range<u64>>,
+
+    // details found in the artefact when carving it
+    pub metadata: Metadata,
+    /*
     // sample bytes from offset
     pub sample: Vec<u8>, */
 }
 
 impl CarvingResult {
     // helper to define a new result
-    pub fn new(offset: u64, file_name: &str, length: usize) -> Self {
+    pub fn new(offset: u64, length: usize) -> Self {
         Self {
             offset,
-            file_name: Some(String::from(file_name)),
-            length
+            length,
+            truncated: false,
+            gaps: Vec::new(),
+            subtype: None,
+            embedded: Vec::new(),
+            metadata: Metadata::new(),
+        }
+    }
+
+    // helper for an artefact which doesn't fit: only its first bytes are kept
+    pub fn truncated(length: usize) -> Self {
+        Self {
+            offset: length as u64,
+            length,
+            truncated: true,
+            gaps: Vec::new(),
+            subtype: None,
+            embedded: Vec::new(),
+            metadata: Metadata::new(),
         }
     }
 }
 
 // carve when the file header contains the file size
+pub mod footer_carver;
 pub mod fourcc_carver;
 pub mod size_carver;
@@ -2,14 +2,24 @@ use std::{fmt::Debug, io::Cursor};
 
 use log::debug;
 
-use crate::{deserializer::Deserializer, filetypes::corpus::FileType};
+use crate::{audit::Metadata, deserializer::Deserializer, filetypes::corpus::FileType};
 
-use super::CarvingResult;
+use super::{CarvingResult, Subtype};
 
 pub trait SizeCarver {
     fn size(&self) -> usize; // size of the file we're trying to carve
     fn is_genuine(&self) -> bool; // true to guess whether what we're carving out could be a genuine file
     fn ext(&self) -> String; // the file extension of what we're trying to carve
+
+    // a more precise file type, found in the header
+    fn subtype(&self) -> Option<Subtype> {
+        None
+    }
+
+    // details found in the header, reported in the audit
+    fn metadata(&self) -> Metadata {
+        Vec::new()
+    }
 }
 
 pub fn carve_using_size<T>(mmap: &[u8], ft: &FileType) -> anyhow::Result<CarvingResult>
@@ -19,8 +29,10 @@ where
     // read magic
     let mut header = T::default();
     let mut cursor = Cursor::new(mmap);
-    header.deserialize(&mut cursor)?;
-    debug!("header={:?}", header);
+    if header.deserialize(&mut cursor).is_err() {
+        return Ok(CarvingResult::default());
+    }
+    debug!("{} header={:?}", header.ext(), header);
 
     // now read remaining data if this appears to be a real file
     if header.is_genuine() {
@@ -29,14 +41,33 @@ where
             return Ok(CarvingResult::default());
         }
 
-        // payload will receive all data
-        let payload = &mmap[..header.size()];
+        // the size comes from the header and could be anything: never go past the maximum size
+        // for this file type
+        if header.size() > ft.max_size && ft.max_size <= mmap.len() {
+            debug!(
+                "file type {}: size {} is over the maximum size {}",
+                &ft.ext,
+                header.size(),
+                ft.max_size
+            );
+            return Ok(CarvingResult::truncated(ft.max_size));
+        }
 
-        // save file
-        let file_name = ft.save_file(payload)?;
+        // nor past the end of the image: the artefact isn't all there
+        if header.size() > mmap.len() {
+            debug!(
+                "file type {}: size {} is past the end of the image",
+                &ft.ext,
+                header.size()
+            );
+            return Ok(CarvingResult::default());
+        }
 
-        // move offset, lock will be automatically released
-        Ok(CarvingResult::new(header.size() as u64, &file_name, payload.len()))
+        // move offset, the file is saved by the caller
+        let mut result = CarvingResult::new(header.size() as u64, header.size());
+        result.subtype = header.subtype();
+        result.metadata = header.metadata();
+        Ok(result)
     } else {
         Ok(CarvingResult::default())
     }
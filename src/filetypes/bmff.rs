@@ -0,0 +1,242 @@
+use std::io::{Cursor, Error, ErrorKind, Read};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::{
+    audit::Metadata,
+    carvers::{Subtype, fourcc_carver::FourCCCarver},
+    deserializer::Deserializer,
+    err,
+};
+
+// ISO base media file format: MP4, QuickTime, 3GP, HEIF, ... are a sequence of boxes, each one
+// starting with its size and type. There's no end marker, the file ends with its last box
+// see: https://mpeg.chiariglione.org/standards/mpeg-4/iso-base-media-file-format
+const FTYP: [u8; 4] = *b"ftyp";
+const MOOV: [u8; 4] = *b"moov";
+const MDAT: [u8; 4] = *b"mdat";
+const META: [u8; 4] = *b"meta";
+
+// the boxes found at the top level of a file
+const TOP_LEVEL: [&[u8; 4]; 16] = [
+    b"ftyp", b"pdin", b"moov", b"moof", b"mfra", b"mdat", b"free", b"skip", b"meta", b"uuid",
+    b"wide", b"styp", b"sidx", b"ssix", b"prft", b"emsg",
+];
+
+pub const MOV: Subtype = Subtype {
+    ext: "mov",
+    category: "videos/mov",
+};
+pub const M4A: Subtype = Subtype {
+    ext: "m4a",
+    category: "audio/m4a",
+};
+pub const GPP: Subtype = Subtype {
+    ext: "3gp",
+    category: "videos/3gp",
+};
+pub const HEIC: Subtype = Subtype {
+    ext: "heic",
+    category: "images/heic",
+};
+pub const AVIF: Subtype = Subtype {
+    ext: "avif",
+    category: "images/avif",
+};
+
+// the file type given by the major brand, MP4 if it's not a more precise one
+fn classify(brand: &[u8; 4]) -> Option<Subtype> {
+    match brand {
+        b"qt  " => Some(MOV),
+        b"M4A " | b"M4B " => Some(M4A),
+        [b'3', b'g', b'p' | b'2', _] => Some(GPP),
+        b"heic" | b"heix" | b"heim" | b"heis" | b"mif1" | b"msf1" => Some(HEIC),
+        b"avif" | b"avis" => Some(AVIF),
+        _ => None,
+    }
+}
+
+// the file type box, which must come first
+#[derive(Debug, Default)]
+pub struct BMFFHeader {
+    size: u32,
+    box_type: [u8; 4], // should be "ftyp"
+}
+
+impl Deserializer for BMFFHeader {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        self.size = buffer.read_u32::<BigEndian>()?;
+        buffer.read_exact(&mut self.box_type)?;
+
+        // major brand, minor version and a list of compatible brands
+        if self.box_type != FTYP || self.size < 16 || !self.size.is_multiple_of(4) {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        // the file type box is read as the first box, to get the brand
+        buffer.set_position(0);
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BMFFBox {
+    size: u64, // including the header, from the 64-bit largesize if size is 1
+    box_type: [u8; 4],
+    brand: [u8; 4], // major brand for the file type box
+}
+
+impl Deserializer for BMFFBox {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let start = buffer.position();
+        self.size = buffer.read_u32::<BigEndian>()? as u64;
+        buffer.read_exact(&mut self.box_type)?;
+
+        // a file type box after the first box starts another file
+        if !TOP_LEVEL.contains(&&self.box_type) || (self.box_type == FTYP && start != 0) {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        let header_len = match self.size {
+            1 => {
+                self.size = buffer.read_u64::<BigEndian>()?;
+                16
+            }
+            // a size of 0 means up to the end of the file, which is unknown here
+            0 => return err!(ErrorKind::InvalidData),
+            _ => 8,
+        };
+        if self.size < header_len {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        if self.box_type == FTYP {
+            buffer.read_exact(&mut self.brand)?;
+        }
+
+        // the largesize could be anything
+        let Some(end) = start.checked_add(self.size) else {
+            return err!(ErrorKind::InvalidData);
+        };
+        buffer.set_position(end);
+        Ok(self.size as usize)
+    }
+}
+
+impl FourCCCarver for BMFFBox {
+    fn is_end(&self) -> bool {
+        false
+    }
+
+    fn subtype(&self) -> Option<Subtype> {
+        if self.box_type == FTYP {
+            classify(&self.brand)
+        } else {
+            None
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        if self.box_type == FTYP {
+            vec![(
+                "brand",
+                String::from_utf8_lossy(&self.brand).trim_end().to_string(),
+            )]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn has_end() -> bool {
+        false
+    }
+
+    fn fourcc(&self) -> Option<[u8; 4]> {
+        Some(self.box_type)
+    }
+
+    // movies have a movie box with the tracks, HEIF images have a meta box with the items
+    fn required(subtype: Option<Subtype>) -> &'static [[u8; 4]] {
+        match subtype {
+            Some(HEIC) | Some(AVIF) => &[META, MDAT],
+            _ => &[MOOV, MDAT],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::carvers::fourcc_carver::fourcc_carver;
+    use crate::filetypes::corpus::Corpus;
+
+    #[test]
+    fn bmff_boxes() {
+        // ftyp, then an mdat using a largesize, then zeroes
+        let data = hex!(
+            "00 00 00 14 66 74 79 70 71 74 20 20 00 00 02 00 71 74 20 20
+             00 00 00 01 6D 64 61 74 00 00 00 00 00 00 00 12 AA BB
+             00 00 00 00 00 00 00 00"
+        );
+        let mut c = Cursor::new(data.as_slice());
+        let mut header = BMFFHeader::default();
+        header.deserialize(&mut c).unwrap();
+        assert_eq!(c.position(), 0);
+
+        let mut ftyp = BMFFBox::default();
+        ftyp.deserialize(&mut c).unwrap();
+        assert_eq!(ftyp.subtype(), Some(MOV));
+        assert_eq!(ftyp.metadata()[0].1, "qt");
+
+        let mut mdat = BMFFBox::default();
+        assert_eq!(mdat.deserialize(&mut c).unwrap(), 18);
+        assert_eq!(mdat.fourcc(), Some(MDAT));
+        assert_eq!(c.position(), 38);
+
+        // not a box
+        assert!(BMFFBox::default().deserialize(&mut c).is_err());
+
+        // another file starts
+        let mut data = data.to_vec();
+        data[24..28].copy_from_slice(&FTYP);
+        let mut c = Cursor::new(data.as_slice());
+        c.set_position(20);
+        assert!(BMFFBox::default().deserialize(&mut c).is_err());
+
+        // a largesize which overflows
+        let mut data = data.clone();
+        data[24..28].copy_from_slice(&MDAT);
+        data[28..36].fill(0xFF);
+        let mut c = Cursor::new(data.as_slice());
+        c.set_position(20);
+        assert!(BMFFBox::default().deserialize(&mut c).is_err());
+
+        assert_eq!(classify(b"heic"), Some(HEIC));
+        assert_eq!(classify(b"3gp5"), Some(GPP));
+        assert_eq!(classify(b"isom"), None);
+        assert_eq!(BMFFBox::required(Some(AVIF)), &[META, MDAT]);
+
+        // an mdat past the maximum size only truncates a movie with a moov
+        let mut corpus = Corpus::new(0);
+        corpus
+            .set_max_sizes(None, &[(String::from("mp4"), 0x40)])
+            .unwrap();
+        let ft = corpus.iter().find(|ft| ft.ext == "mp4").unwrap();
+        let mut data = hex!(
+            "00 00 00 14 66 74 79 70 71 74 20 20 00 00 02 00 71 74 20 20
+             00 00 00 08 6D 6F 6F 76 00 00 01 00 6D 64 61 74"
+        )
+        .to_vec();
+        data.resize(0x200, 0);
+        let result = fourcc_carver::<BMFFHeader, BMFFBox>(&data, ft).unwrap();
+        assert!(result.truncated);
+        assert_eq!(result.length, 0x40);
+
+        data[24..28].copy_from_slice(b"free");
+        let result = fourcc_carver::<BMFFHeader, BMFFBox>(&data, ft).unwrap();
+        assert!(!result.truncated);
+        assert_eq!(result.length, 0);
+    }
+}
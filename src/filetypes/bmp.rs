@@ -3,6 +3,7 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use crate::{carvers::size_carver::SizeCarver, deserializer::Deserializer};
 
 // see: https://www.ece.ualberta.ca/~elliott/ee552/studentAppNotes/2003_w/misc/bmp_file_format/bmp_file_format.htm
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Default)]
 pub struct BMP {
     magic: u16,             // should be 'BM'
@@ -1,42 +1,79 @@
 use std::{
     fs::{self, File},
     io::{BufWriter, Write},
-    ops::{Deref, Index},
+    ops::Deref,
     path::Path,
-    sync::Mutex,
-    usize,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
 };
 
 use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
 use hex_literal::hex;
 
 use crate::{
-    carvers::{CarvingResult, fourcc_carver::fourcc_carver, size_carver::carve_using_size},
-    filetypes::{bmp::BMP, wav::WAV},
+    audit::Metadata,
+    carvers::{
+        CarvingResult, Subtype, footer_carver::carve_using_footer, fourcc_carver::fourcc_carver,
+        size_carver::carve_using_size,
+    },
+    filetypes::{bmp::BMP, riff::RIFF},
+    validation::Validation,
 };
 
 use super::{
+    bmff::{BMFFBox, BMFFHeader},
+    ebml::{EBMLElement, EBMLHeader},
+    elf::ELF,
+    flac::{FlacBlock, FlacHeader},
+    gif::{GIFBlock, GIFHeader},
     jpeg::JpegSegment,
-    png::{PNGChunk, PNGHeader},
+    jpeg_decoder,
+    mp3::carve_mp3,
+    ogg::{OggHeader, OggPage},
+    pdf::carve_pdf,
+    pe::PE,
+    png::{self, PNGChunk, PNGHeader},
+    sqlite::{SQLite, WAL},
+    tiff::TIFF,
+    zip::{ZipHeader, ZipRecord},
 };
 
 // alias for the carving function depending on the file type
 pub type CarvingFunc = fn(&[u8], &FileType) -> anyhow::Result<CarvingResult>;
 
+// alias for the function checking the content of a carved artefact
+pub type ValidationFunc = fn(&[u8], &mut Metadata) -> Validation;
+
 // carving mode
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub enum CarvingMethod {
     #[default]
     Strict, // respect the file structure
     Simple, // carve between a header and a footer
-    Fancy,  // follows the file structure
+    Fancy,  // follows the file structure, skipping over damaged parts
+}
+
+// the end of an artefact when carving between a header and a footer
+#[derive(Debug)]
+pub enum Footer {
+    Include(Vec<u8>), // the artefact ends right after these bytes
+}
+
+// how carved files are named
+#[derive(Debug, Default, Clone, Copy)]
+pub enum FileNaming {
+    #[default]
+    Index, // a counter per file type, depends on the order threads find the artefacts
+    Offset, // the offset of the artefact in the image, stable across runs
 }
 
 // define what we're going to search for
 #[derive(Debug)]
 pub struct FileType {
-    // the magic bytes to look for
-    pub magic: Vec<u8>,
+    // the magic bytes to look for, several ones if the file type has variants
+    pub magics: Vec<Vec<u8>>,
 
     // the file type extension
     pub ext: String,
@@ -56,38 +93,93 @@ pub struct FileType {
     // the current index of the file being carved
     pub index: Mutex<usize>,
 
+    // the maximum number of files to carve for this file type
+    pub limit: Option<usize>,
+
+    // the number of files carved so far for this file type, for all threads
+    pub nb_files: AtomicUsize,
+
     // the method used to carve
     pub carving_method: CarvingMethod,
+
+    // the footer used by the Simple carving method, if any
+    pub footer: Option<Footer>,
+
+    // the function used to check the content of carved artefacts, if any
+    pub validation_func: Option<ValidationFunc>,
+
+    // true if the magic is found at each frame (e.g.: MP3 frame headers): matches inside an
+    // artefact of this type are not nested files
+    pub repeated_magic: bool,
 }
 
 impl FileType {
+    // try to carve an artefact at the beginning of mmap. Files are carved between a header
+    // and a footer if it's the method required and a footer is known, using their structure otherwise
+    pub fn carve(&self, mmap: &[u8]) -> anyhow::Result<CarvingResult> {
+        match self.carving_method {
+            CarvingMethod::Simple if self.footer.is_some() => carve_using_footer(mmap, self),
+            _ => (self.carving_func)(mmap, self),
+        }
+    }
+
+    // build the name of the next file to save, depending on the naming scheme. With the offset
+    // scheme, the name only depends on where the artefact was found in the image. If carving
+    // found a more precise file type, its extension and category are used
+    pub fn file_name(&self, offset: u64, naming: &FileNaming, subtype: Option<&Subtype>) -> String {
+        let (ext, category) = match subtype {
+            Some(subtype) => (subtype.ext, subtype.category),
+            None => (self.ext.as_str(), self.category.as_str()),
+        };
+
+        match naming {
+            FileNaming::Index => {
+                let mut index = self.index.lock().unwrap();
+                let file_name = format!("{}/{}_{:08}.{}", category, ext, index, ext);
+
+                // add 1 to our per extension counter
+                *index += 1;
+
+                file_name
+            }
+            FileNaming::Offset => format!("{}/{}_0x{:08X}.{}", category, ext, offset, ext),
+        }
+    }
+
+    // book one more file for this file type, unless its limit is already reached
+    pub fn reserve(&self) -> bool {
+        match self.limit {
+            Some(limit) => reserve(&self.nb_files, limit),
+            None => {
+                self.nb_files.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+
+    // true if no more files of this type can be carved
+    pub fn is_full(&self) -> bool {
+        self.limit
+            .is_some_and(|limit| self.nb_files.load(Ordering::Relaxed) >= limit)
+    }
+
     // helper function to save the carved file
-    pub fn save_file(&self, payload: &[u8]) -> anyhow::Result<String> {
+    pub fn save_file(&self, payload: &[u8], file_name: &str) -> anyhow::Result<()> {
         // test sub-directory for category: check if the directory exists
-        if !Path::new(&self.category).exists() {
+        if let Some(category) = Path::new(file_name).parent()
+            && !category.exists()
+        {
             // create the directory including subdir
-            fs::create_dir_all(&self.category)?;
+            fs::create_dir_all(category)?;
         }
 
-        // now we can build file name
-        let file_name = format!(
-            "{}/{}_{:08}.{}",
-            self.category,
-            self.ext,
-            self.index.lock().unwrap(),
-            self.ext
-        );
-
-        let file = File::create(&file_name)?;
+        let file = File::create(file_name)?;
         let mut writer = BufWriter::new(file);
 
         writer.write_all(payload)?;
         writer.flush()?; // Ensure everything is written
 
-        // add 1 to our per extension counter
-        *self.index.lock().unwrap() += 1;
-
-        Ok(file_name)
+        Ok(())
     }
 }
 
@@ -103,50 +195,309 @@ impl Corpus {
 
         // BMP
         vec.push(FileType {
-            magic: b"BM".to_vec(),
+            magics: vec![b"BM".to_vec()],
             ext: String::from("bmp"),
             carving_func: carve_using_size::<BMP>,
             category: String::from("images/bmp"),
-            min_size: min_size,
-            max_size: 1000000,
+            min_size,
+            max_size: 100_000_000,
             index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
             carving_method: CarvingMethod::Simple,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
         });
 
-        // WAV
+        // RIFF: WAV, and the other form types (avi, webp, ani, ...). RF64 is for WAV over 4 GB
         vec.push(FileType {
-            magic: b"RIFF".to_vec(),
+            magics: vec![b"RIFF".to_vec(), b"RF64".to_vec()],
             ext: String::from("wav"),
-            carving_func: carve_using_size::<WAV>,
+            carving_func: carve_using_size::<RIFF>,
             category: String::from("audio/wav"),
-            min_size: min_size,
-            max_size: 1000000,
+            min_size,
+            // RF64 sizes are 64-bit: saturate on 32-bit targets
+            max_size: usize::try_from(16_000_000_000u64).unwrap_or(usize::MAX),
             index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
             carving_method: CarvingMethod::Simple,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
         });
 
         // PNG
         vec.push(FileType {
-            magic: hex!("89 50 4E 47 0D 0A 1A 0A").to_vec(),
+            magics: vec![hex!("89 50 4E 47 0D 0A 1A 0A").to_vec()],
             ext: String::from("png"),
             carving_func: fourcc_carver::<PNGHeader, PNGChunk>,
             category: String::from("images/png"),
-            min_size: min_size,
-            max_size: 1000000,
+            min_size,
+            max_size: 50_000_000,
             index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
             carving_method: CarvingMethod::Simple,
+            footer: None,
+            validation_func: Some(png::validate),
+            repeated_magic: false,
+        });
+
+        // GIF
+        vec.push(FileType {
+            magics: vec![b"GIF8".to_vec()],
+            ext: String::from("gif"),
+            carving_func: fourcc_carver::<GIFHeader, GIFBlock>,
+            category: String::from("images/gif"),
+            min_size,
+            max_size: 20_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: Some(Footer::Include(hex!("00 3B").to_vec())),
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // TIFF, and camera RAW files based on TIFF
+        vec.push(FileType {
+            magics: vec![
+                b"II\x2A\x00".to_vec(),
+                b"MM\x00\x2A".to_vec(),
+                b"II\x2B\x00".to_vec(),
+                b"MM\x00\x2B".to_vec(),
+            ],
+            ext: String::from("tif"),
+            carving_func: carve_using_size::<TIFF>,
+            category: String::from("images/tif"),
+            min_size,
+            max_size: 200_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
         });
 
         // JPEG
         vec.push(FileType {
-            magic: hex!("FF D8 FF").to_vec(),
+            magics: vec![hex!("FF D8 FF").to_vec()],
             ext: String::from("jpg"),
             carving_func: fourcc_carver::<JpegSegment, JpegSegment>,
             category: String::from("images/jpg"),
-            min_size: min_size,
-            max_size: 1000000,
+            min_size,
+            max_size: 50_000_000,
             index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
             carving_method: CarvingMethod::Strict,
+            footer: Some(Footer::Include(hex!("FF D9").to_vec())),
+            validation_func: Some(jpeg_decoder::validate),
+            repeated_magic: false,
+        });
+
+        // ZIP, and the file types based on ZIP (docx, odt, jar, ...)
+        vec.push(FileType {
+            magics: vec![hex!("50 4B 03 04").to_vec()],
+            ext: String::from("zip"),
+            carving_func: fourcc_carver::<ZipHeader, ZipRecord>,
+            category: String::from("archives/zip"),
+            min_size,
+            max_size: 500_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // ISO base media files (mp4, mov, 3gp, heic, ...): the file type box comes first, but
+        // the magic is its type, after its size
+        vec.push(FileType {
+            magics: (0x10..=0x7C)
+                .step_by(4)
+                .map(|size| [0, 0, 0, size, b'f', b't', b'y', b'p'].to_vec())
+                .collect(),
+            ext: String::from("mp4"),
+            carving_func: fourcc_carver::<BMFFHeader, BMFFBox>,
+            category: String::from("videos/mp4"),
+            min_size,
+            max_size: 4_000_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // MP3: an ID3v2 tag, or the first MPEG-1, MPEG-2 or MPEG-2.5 Layer III frame
+        vec.push(FileType {
+            magics: vec![
+                b"ID3".to_vec(),
+                hex!("FF FB").to_vec(),
+                hex!("FF FA").to_vec(),
+                hex!("FF F3").to_vec(),
+                hex!("FF F2").to_vec(),
+                hex!("FF E3").to_vec(),
+                hex!("FF E2").to_vec(),
+            ],
+            ext: String::from("mp3"),
+            carving_func: carve_mp3,
+            category: String::from("audio/mp3"),
+            min_size,
+            max_size: 500_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: true,
+        });
+
+        // FLAC
+        vec.push(FileType {
+            magics: vec![b"fLaC".to_vec()],
+            ext: String::from("flac"),
+            carving_func: fourcc_carver::<FlacHeader, FlacBlock>,
+            category: String::from("audio/flac"),
+            min_size,
+            max_size: 1_000_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // Ogg: Vorbis, and the other codecs (opus, flac, speex, theora)
+        vec.push(FileType {
+            magics: vec![b"OggS".to_vec()],
+            ext: String::from("ogg"),
+            carving_func: fourcc_carver::<OggHeader, OggPage>,
+            category: String::from("audio/ogg"),
+            min_size,
+            max_size: 1_000_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: true,
+        });
+
+        // Matroska and WebM
+        vec.push(FileType {
+            magics: vec![hex!("1A 45 DF A3").to_vec()],
+            ext: String::from("mkv"),
+            carving_func: fourcc_carver::<EBMLHeader, EBMLElement>,
+            category: String::from("videos/mkv"),
+            min_size,
+            max_size: 4_000_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // PDF
+        vec.push(FileType {
+            magics: vec![b"%PDF-".to_vec()],
+            ext: String::from("pdf"),
+            carving_func: carve_pdf,
+            category: String::from("documents/pdf"),
+            min_size,
+            max_size: 500_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: Some(Footer::Include(b"%%EOF".to_vec())),
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // SQLite database
+        vec.push(FileType {
+            magics: vec![b"SQLite format 3\0".to_vec()],
+            ext: String::from("sqlite"),
+            carving_func: carve_using_size::<SQLite>,
+            category: String::from("databases/sqlite"),
+            min_size,
+            max_size: 1_000_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // SQLite write-ahead log, the -wal file next to a database
+        vec.push(FileType {
+            magics: vec![hex!("37 7F 06 82").to_vec(), hex!("37 7F 06 83").to_vec()],
+            ext: String::from("wal"),
+            carving_func: carve_using_size::<WAL>,
+            category: String::from("databases/wal"),
+            min_size,
+            max_size: 1_000_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // PE executables and libraries
+        vec.push(FileType {
+            magics: vec![b"MZ".to_vec()],
+            ext: String::from("exe"),
+            carving_func: carve_using_size::<PE>,
+            category: String::from("executables/exe"),
+            min_size,
+            max_size: 4_000_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
+        });
+
+        // ELF executables, shared objects, object files and core dumps
+        vec.push(FileType {
+            magics: vec![b"\x7FELF".to_vec()],
+            ext: String::from("elf"),
+            carving_func: carve_using_size::<ELF>,
+            category: String::from("executables/elf"),
+            min_size,
+            max_size: 4_000_000_000,
+            index: Mutex::new(0),
+            limit: None,
+            nb_files: AtomicUsize::new(0),
+            carving_method: CarvingMethod::Strict,
+            footer: None,
+            validation_func: None,
+            repeated_magic: false,
         });
 
         Self(vec)
@@ -154,14 +505,88 @@ impl Corpus {
 
     // list of patterns to give to the Aho-Corasick algorithm
     // basically, it's the list of magic bytes
-    pub fn patterns(&self) -> anyhow::Result<AhoCorasick> {
+    pub fn patterns(&self) -> anyhow::Result<Patterns> {
         // Define binary patterns to search for
-        let patterns: Vec<_> = self.0.iter().map(|ftype| ftype.magic.clone()).collect();
+        let patterns: Vec<_> = self.0.iter().flat_map(|ftype| &ftype.magics).collect();
 
         // Build the Aho-Corasick automaton
         let ac = AhoCorasickBuilder::new().build(&patterns)?;
 
-        Ok(ac)
+        // the file type of each pattern, in the same order
+        let file_types = self
+            .0
+            .iter()
+            .enumerate()
+            .flat_map(|(i, ftype)| ftype.magics.iter().map(move |_| i))
+            .collect();
+
+        Ok(Patterns { ac, file_types })
+    }
+
+    // length of the longest magic, used to overlap the chunks searched by the threads
+    pub fn max_magic_len(&self) -> usize {
+        self.0
+            .iter()
+            .flat_map(|ftype| &ftype.magics)
+            .map(|magic| magic.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    // set the maximum number of files to carve for the file types found in the list passed
+    pub fn set_limits(&mut self, limits: &[(String, usize)]) -> anyhow::Result<()> {
+        for (ext, limit) in limits {
+            let mut found = false;
+            for ft in self.0.iter_mut().filter(|ft| &ft.ext == ext) {
+                ft.limit = Some(*limit);
+                found = true;
+            }
+            if !found {
+                anyhow::bail!("unknown extension in limits: {}", ext);
+            }
+        }
+        Ok(())
+    }
+
+    // set the maximum size of the artefacts, either for all file types or for the file types
+    // found in the list passed
+    pub fn set_max_sizes(
+        &mut self,
+        max_size: Option<usize>,
+        max_sizes: &[(String, usize)],
+    ) -> anyhow::Result<()> {
+        if let Some(max_size) = max_size {
+            for ft in self.0.iter_mut() {
+                ft.max_size = max_size;
+            }
+        }
+
+        for (ext, max_size) in max_sizes {
+            let mut found = false;
+            for ft in self.0.iter_mut().filter(|ft| &ft.ext == ext) {
+                ft.max_size = *max_size;
+                found = true;
+            }
+            if !found {
+                anyhow::bail!("unknown extension in maximum sizes: {}", ext);
+            }
+        }
+        Ok(())
+    }
+
+    // force the carving method for all file types
+    pub fn set_carving_method(&mut self, method: CarvingMethod) {
+        for ft in self.0.iter_mut() {
+            ft.carving_method = method;
+        }
+    }
+
+    // carve to a directory other than the current one. Subtypes keep their own category
+    #[cfg(test)]
+    pub fn set_output_dir(&mut self, dir: &Path) {
+        for ft in &mut self.0 {
+            ft.category = dir.join(&ft.category).to_string_lossy().to_string();
+        }
     }
 
     // only keep the extensions found in the list passed
@@ -172,6 +597,30 @@ impl Corpus {
     }
 }
 
+// the Aho-Corasick engine looking for the magics of all file types
+#[derive(Debug)]
+pub struct Patterns {
+    pub ac: AhoCorasick,
+    file_types: Vec<usize>, // index in the corpus of the file type of each pattern
+}
+
+impl Patterns {
+    // index of the file type having the pattern found by the Aho-Corasick engine
+    pub fn index_of(&self, pattern: usize) -> usize {
+        self.file_types[pattern]
+    }
+}
+
+// add 1 to the counter, unless it already reached the limit. Several threads could book the
+// last slot at the same time, so increment only if it's still possible
+pub fn reserve(counter: &AtomicUsize, limit: usize) -> bool {
+    counter
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            (n < limit).then_some(n + 1)
+        })
+        .is_ok()
+}
+
 // impl Index<usize> for Corpus {
 //     type Output = Option<String>;
 
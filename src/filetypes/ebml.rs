@@ -0,0 +1,304 @@
+use std::io::{Cursor, Error, ErrorKind};
+
+use byteorder::ReadBytesExt;
+
+use crate::{
+    audit::Metadata,
+    carvers::{Subtype, fourcc_carver::FourCCCarver},
+    deserializer::Deserializer,
+    err,
+};
+
+// Matroska and WebM files are EBML: elements made of a variable-length ID, a variable-length size
+// and data. The file is an EBML header followed by a Segment holding everything else
+// see: https://www.rfc-editor.org/rfc/rfc8794 and https://www.rfc-editor.org/rfc/rfc9559
+const EBML: u32 = 0x1A45DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const CLUSTER: u32 = 0x1F43B675;
+
+// the elements found in a Segment
+const LEVEL_1: [u32; 8] = [
+    0x114D9B74, // SeekHead
+    0x1549A966, // Info
+    0x1654AE6B, // Tracks
+    CLUSTER,    // Cluster
+    0x1C53BB6B, // Cues
+    0x1941A469, // Attachments
+    0x1043A770, // Chapters
+    0x1254C367, // Tags
+];
+
+// the elements found anywhere
+const GLOBAL: [u32; 2] = [
+    0xEC, // Void
+    0xBF, // CRC-32
+];
+
+// the elements found in a Cluster
+const CLUSTER_CHILDREN: [u32; 7] = [
+    0xE7,   // Timestamp
+    0x5854, // SilentTracks
+    0xA7,   // Position
+    0xAB,   // PrevSize
+    0xA3,   // SimpleBlock
+    0xA0,   // BlockGroup
+    0xAF,   // EncryptedBlock
+];
+
+pub const WEBM: Subtype = Subtype {
+    ext: "webm",
+    category: "videos/webm",
+};
+
+// read a variable-length integer: the number of leading zeros of the first byte gives its
+// length. IDs keep the length marker, sizes don't
+fn vint(
+    buffer: &mut Cursor<&[u8]>,
+    max_len: u32,
+    keep_marker: bool,
+) -> std::io::Result<(u64, u32)> {
+    let first = buffer.read_u8()?;
+    let len = first.leading_zeros() + 1;
+    if len > max_len {
+        return err!(ErrorKind::InvalidData);
+    }
+
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        first as u64 & (0xFF >> len)
+    };
+    for _ in 1..len {
+        value = (value << 8) | buffer.read_u8()? as u64;
+    }
+    Ok((value, len))
+}
+
+fn element_id(buffer: &mut Cursor<&[u8]>) -> std::io::Result<u32> {
+    vint(buffer, 4, true).map(|(id, _)| id as u32)
+}
+
+// the size of an element, None if unknown: all its bits are set
+fn element_size(buffer: &mut Cursor<&[u8]>) -> std::io::Result<Option<u64>> {
+    let (size, len) = vint(buffer, 8, false)?;
+    Ok((size != (1 << (7 * len)) - 1).then_some(size))
+}
+
+// the DocType found in the EBML header, which is at the start of buffer
+fn doc_type(buffer: &mut Cursor<&[u8]>) -> std::io::Result<String> {
+    if element_id(buffer)? != EBML {
+        return err!(ErrorKind::InvalidData);
+    }
+    let Some(size) = element_size(buffer)? else {
+        return err!(ErrorKind::InvalidData);
+    };
+    let end = buffer.position() + size;
+
+    while buffer.position() < end {
+        let id = element_id(buffer)?;
+        let Some(size) = element_size(buffer)? else {
+            return err!(ErrorKind::InvalidData);
+        };
+        let start = buffer.position() as usize;
+        buffer.set_position(start as u64 + size);
+
+        if id == DOC_TYPE {
+            let value = buffer
+                .get_ref()
+                .get(start..start + size as usize)
+                .ok_or(Error::from(ErrorKind::UnexpectedEof))?;
+            let doc_type = String::from_utf8_lossy(value)
+                .trim_end_matches('\0')
+                .to_string();
+            if doc_type != "matroska" && doc_type != "webm" {
+                return err!(ErrorKind::InvalidData);
+            }
+            buffer.set_position(end);
+            return Ok(doc_type);
+        }
+    }
+
+    err!(ErrorKind::InvalidData)
+}
+
+// the EBML header, with a DocType we know
+#[derive(Debug, Default)]
+pub struct EBMLHeader {
+    doc_type: String,
+}
+
+impl Deserializer for EBMLHeader {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        self.doc_type = doc_type(buffer)?;
+
+        // the header is read as the first element, to get the DocType
+        buffer.set_position(0);
+        Ok(0)
+    }
+}
+
+// a top-level element, or an element of a Segment or a Cluster whose size is unknown
+#[derive(Debug, Default)]
+pub struct EBMLElement {
+    id: u32,
+    size: Option<u64>,
+    doc_type: String, // for the EBML header
+    cluster: bool,    // inside a Cluster whose size is unknown, where its children are walked
+}
+
+impl Deserializer for EBMLElement {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let start = buffer.position();
+        self.id = element_id(buffer)?;
+
+        if self.id == EBML && start == 0 {
+            buffer.set_position(0);
+            self.doc_type = doc_type(buffer)?;
+            return Ok(buffer.position() as usize);
+        }
+
+        let level_1 = self.id == SEGMENT || LEVEL_1.contains(&self.id);
+        let child = self.cluster && CLUSTER_CHILDREN.contains(&self.id);
+        if !(level_1 || child || GLOBAL.contains(&self.id)) {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        self.size = element_size(buffer)?;
+        if level_1 {
+            self.cluster = self.id == CLUSTER && self.size.is_none();
+        }
+        match self.size {
+            Some(size) => buffer.set_position(buffer.position() + size),
+
+            // live recordings don't know the Segment and Cluster sizes when writing them: their
+            // children are walked instead
+            None if self.id == SEGMENT || self.id == CLUSTER => (),
+            None => return err!(ErrorKind::InvalidData),
+        }
+
+        Ok((buffer.position() - start) as usize)
+    }
+}
+
+impl FourCCCarver for EBMLElement {
+    // a Segment with a size holds the whole file
+    fn is_end(&self) -> bool {
+        self.id == SEGMENT && self.size.is_some()
+    }
+
+    fn subtype(&self) -> Option<Subtype> {
+        (self.doc_type == "webm").then_some(WEBM)
+    }
+
+    fn metadata(&self) -> Metadata {
+        if self.id == SEGMENT && self.size.is_none() {
+            vec![("segment_size", String::from("unknown"))]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn has_end() -> bool {
+        false
+    }
+
+    fn fourcc(&self) -> Option<[u8; 4]> {
+        Some(self.id.to_be_bytes())
+    }
+
+    fn required(_subtype: Option<Subtype>) -> &'static [[u8; 4]] {
+        const REQUIRED: [[u8; 4]; 1] = [SEGMENT.to_be_bytes()];
+        &REQUIRED
+    }
+
+    // the children of a Cluster are only found in one
+    fn carry(&mut self, previous: &Self) {
+        self.cluster = previous.cluster;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    // EBML header for WebM
+    const HEADER: [u8; 24] =
+        hex!("1A 45 DF A3 93 42 86 81 01 42 F7 81 01 42 82 84 77 65 62 6D 42 87 81 02");
+
+    fn walk(data: &[u8]) -> Vec<EBMLElement> {
+        let mut c = Cursor::new(data);
+        let mut elements: Vec<EBMLElement> = Vec::new();
+        loop {
+            let mut element = EBMLElement::default();
+            if let Some(previous) = elements.last() {
+                element.carry(previous);
+            }
+            if element.deserialize(&mut c).is_err() {
+                break;
+            }
+            let end = element.is_end();
+            elements.push(element);
+            if end {
+                break;
+            }
+        }
+        elements
+    }
+
+    #[test]
+    fn ebml_elements() {
+        let mut c = Cursor::new(HEADER.as_slice());
+        assert_eq!(vint(&mut c, 4, true).unwrap(), (0x1A45DFA3, 4));
+        assert_eq!(element_size(&mut c).unwrap(), Some(0x13));
+        let mut c = Cursor::new(hex!("01 FF FF FF FF FF FF FF").as_slice());
+        assert_eq!(element_size(&mut c).unwrap(), None);
+
+        let mut header = EBMLHeader::default();
+        header
+            .deserialize(&mut Cursor::new(HEADER.as_slice()))
+            .unwrap();
+        assert_eq!(header.doc_type, "webm");
+
+        // a Segment with a size
+        let mut data = HEADER.to_vec();
+        data.extend(hex!("18 53 80 67 40 06 EC 84 00 00 00 00 1A 45"));
+        let elements = walk(&data);
+        assert_eq!(elements[0].subtype(), Some(WEBM));
+        assert!(elements[1].is_end());
+
+        // a live recording: unknown Segment and Cluster sizes, up to the next file
+        let mut data = HEADER.to_vec();
+        data.extend(hex!("18 53 80 67 01 FF FF FF FF FF FF FF"));
+        data.extend(hex!("1F 43 B6 75 FF E7 81 00 A3 84 81 00 00 80"));
+        data.extend(hex!("1F 43 B6 75 FF E7 81 10 A3 82 81 00"));
+        let len = data.len();
+        data.extend(HEADER);
+        let elements = walk(&data);
+        assert_eq!(elements.len(), 8);
+        assert_eq!(elements[1].metadata()[0].1, "unknown");
+        let mut c = Cursor::new(data.as_slice());
+        c.set_position(len as u64);
+        assert!(EBMLElement::default().deserialize(&mut c).is_err());
+
+        // Cluster children outside of a Cluster
+        let mut data = HEADER.to_vec();
+        data.extend(hex!("18 53 80 67 01 FF FF FF FF FF FF FF"));
+        data.extend(hex!("E7 81 00 A3 82 81 00"));
+        assert_eq!(walk(&data).len(), 2);
+        let mut data = HEADER.to_vec();
+        data.extend(hex!("18 53 80 67 01 FF FF FF FF FF FF FF"));
+        data.extend(hex!("1F 43 B6 75 FF E7 81 00 1C 53 BB 6B 80 A3 82 81 00"));
+        assert_eq!(walk(&data).len(), 5);
+
+        // Void in a Cluster
+        let mut data = HEADER.to_vec();
+        data.extend(hex!("18 53 80 67 01 FF FF FF FF FF FF FF"));
+        data.extend(hex!(
+            "1F 43 B6 75 FF E7 81 00 EC 81 00 A3 82 81 00 BF 84 00 00 00 00"
+        ));
+        assert_eq!(walk(&data).len(), 7);
+    }
+}
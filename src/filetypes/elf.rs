@@ -0,0 +1,319 @@
+// ELF files (executables, shared objects, object files and core dumps) start with a header giving
+// the class (32 or 64-bit), the byte order, and the offsets of the program header table, which
+// describes the segments, and of the section header table
+// see: https://refspecs.linuxfoundation.org/elf/gabi4+/ch4.eheader.html
+use std::io::{Cursor, Error, ErrorKind};
+
+use crate::{audit::Metadata, carvers::size_carver::SizeCarver, deserializer::Deserializer, err};
+
+const MAGIC: &[u8; 4] = b"\x7FELF";
+const CURRENT_VERSION: u32 = 1;
+
+// the numbers of sections and segments don't fit in the header: they're in the first section
+const PN_XNUM: u16 = 0xFFFF;
+
+// a section which takes no space in the file, like .bss
+const SHT_NOBITS: u32 = 8;
+
+// the name of a machine
+fn machine(machine: u16) -> Option<&'static str> {
+    match machine {
+        2 => Some("sparc"),
+        3 => Some("i386"),
+        4 => Some("m68k"),
+        8 => Some("mips"),
+        20 => Some("powerpc"),
+        21 => Some("powerpc64"),
+        22 => Some("s390"),
+        40 => Some("arm"),
+        42 => Some("superh"),
+        43 => Some("sparcv9"),
+        50 => Some("ia64"),
+        62 => Some("x86_64"),
+        183 => Some("aarch64"),
+        243 => Some("riscv"),
+        258 => Some("loongarch"),
+        _ => None,
+    }
+}
+
+// the name of an object file type
+fn elf_type(elf_type: u16) -> Option<&'static str> {
+    match elf_type {
+        1 => Some("REL"),
+        2 => Some("EXEC"),
+        3 => Some("DYN"),
+        4 => Some("CORE"),
+        _ => None,
+    }
+}
+
+// an ELF file, at the start of data
+struct Elf<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+    class64: bool,
+}
+
+impl<'a> Elf<'a> {
+    // check the magic number, the class and the byte order
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let ident = data.get(..16)?;
+        if &ident[..4] != MAGIC || ident[6] != CURRENT_VERSION as u8 || ident[9..] != [0; 7] {
+            return None;
+        }
+
+        let class64 = match ident[4] {
+            1 => false,
+            2 => true,
+            _ => return None,
+        };
+        let little_endian = match ident[5] {
+            1 => true,
+            2 => false,
+            _ => return None,
+        };
+
+        Some(Self {
+            data,
+            little_endian,
+            class64,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        self.uint(offset, 2).map(|v| v as u16)
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        self.uint(offset, 4).map(|v| v as u32)
+    }
+
+    // an address, an offset or a size, which are 64-bit for the 64-bit class
+    fn word(&self, offset: usize) -> Option<u64> {
+        self.uint(offset, if self.class64 { 8 } else { 4 })
+    }
+
+    // read an unsigned integer of len bytes using the byte order
+    fn uint(&self, offset: usize, len: usize) -> Option<u64> {
+        let bytes = self.data.get(offset..offset.checked_add(len)?)?;
+        let fold = |acc: u64, b: &u8| (acc << 8) | *b as u64;
+        if self.little_endian {
+            Some(bytes.iter().rev().fold(0, fold))
+        } else {
+            Some(bytes.iter().fold(0, fold))
+        }
+    }
+
+    // pick the offset of a field for the class
+    fn field(&self, offset32: usize, offset64: usize) -> usize {
+        if self.class64 { offset64 } else { offset32 }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default)]
+pub struct ELF {
+    machine: u16,  // the architecture
+    elf_type: u16, // relocatable, executable, shared object or core dump
+    size: u64,     // the end of the header tables, the segments and the sections
+}
+
+impl SizeCarver for ELF {
+    fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    fn is_genuine(&self) -> bool {
+        machine(self.machine).is_some() && elf_type(self.elf_type).is_some()
+    }
+
+    fn ext(&self) -> String {
+        String::from("elf")
+    }
+
+    fn metadata(&self) -> Metadata {
+        vec![
+            (
+                "machine",
+                machine(self.machine).unwrap_or("unknown").to_string(),
+            ),
+            (
+                "type",
+                elf_type(self.elf_type).unwrap_or("unknown").to_string(),
+            ),
+        ]
+    }
+}
+
+impl Deserializer for ELF {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let Some(elf) = Elf::new(buffer.get_ref()) else {
+            return err!(ErrorKind::InvalidData);
+        };
+        let eof = || Error::from(ErrorKind::UnexpectedEof);
+
+        self.elf_type = elf.u16(16).ok_or_else(eof)?;
+        self.machine = elf.u16(18).ok_or_else(eof)?;
+        let version = elf.u32(20).ok_or_else(eof)?;
+        let phoff = elf.word(elf.field(28, 32)).ok_or_else(eof)?;
+        let shoff = elf.word(elf.field(32, 40)).ok_or_else(eof)?;
+        let ehsize = elf.u16(elf.field(40, 52)).ok_or_else(eof)?;
+        let phentsize = elf.u16(elf.field(42, 54)).ok_or_else(eof)?;
+        let mut phnum = elf.u16(elf.field(44, 56)).ok_or_else(eof)? as u64;
+        let shentsize = elf.u16(elf.field(46, 58)).ok_or_else(eof)?;
+        let mut shnum = elf.u16(elf.field(48, 60)).ok_or_else(eof)? as u64;
+
+        // the sizes of the header and of the table entries are fixed by the class
+        let (header_len, ph_len, sh_len) = if elf.class64 {
+            (64, 56, 64)
+        } else {
+            (52, 32, 40)
+        };
+        if version != CURRENT_VERSION
+            || ehsize != header_len
+            || (phoff != 0 && phentsize != ph_len)
+            || (shoff != 0 && shentsize != sh_len)
+            || (phoff == 0 && shoff == 0)
+        {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        // the tables are in the file
+        if phoff > elf.data.len() as u64 || shoff > elf.data.len() as u64 {
+            return err!(ErrorKind::UnexpectedEof);
+        }
+
+        // fields of a section header, in its table
+        let section = |index: u64, offset32: usize, offset64: usize| {
+            let entry = shoff.checked_add(index.checked_mul(sh_len as u64)?)? as usize;
+            elf.word(entry.checked_add(elf.field(offset32, offset64))?)
+        };
+
+        // numbers which don't fit are in the first section: the size for the sections, and the
+        // info field, which is 32-bit, for the segments
+        if shoff != 0 && shnum == 0 {
+            shnum = section(0, 20, 32).ok_or_else(eof)?;
+        }
+        if shoff != 0 && phnum == PN_XNUM as u64 {
+            let info = (shoff as usize).checked_add(elf.field(28, 44));
+            phnum = info.and_then(|info| elf.u32(info)).ok_or_else(eof)? as u64;
+        }
+
+        let mut size = header_len as u64;
+        if phoff != 0 {
+            size = size.max(phoff.saturating_add(phnum * ph_len as u64));
+        }
+        if shoff != 0 {
+            size = size.max(shoff.saturating_add(shnum.saturating_mul(sh_len as u64)));
+        }
+
+        // the tables are read next
+        if size > elf.data.len() as u64 {
+            return err!(ErrorKind::UnexpectedEof);
+        }
+
+        // the segments, which are all core dumps have
+        for index in 0..phnum {
+            let entry = (phoff + index * ph_len as u64) as usize;
+            let offset = elf.word(entry + elf.field(4, 8)).ok_or_else(eof)?;
+            let filesz = elf.word(entry + elf.field(16, 32)).ok_or_else(eof)?;
+            size = size.max(offset.saturating_add(filesz));
+        }
+
+        for index in 0..shnum {
+            let entry = (shoff + index * sh_len as u64) as usize;
+            let sh_type = elf.u32(entry + 4).ok_or_else(eof)?;
+            let offset = section(index, 16, 24).ok_or_else(eof)?;
+            let len = section(index, 20, 32).ok_or_else(eof)?;
+            if sh_type != SHT_NOBITS {
+                size = size.max(offset.saturating_add(len));
+            }
+        }
+        self.size = size;
+
+        buffer.set_position(header_len as u64);
+        Ok(header_len as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 64-bit little endian executable with one segment and three sections, the last one
+    // being .bss, and a 32-bit big endian object file with two sections
+    #[test]
+    fn elf_tables() {
+        let mut data = vec![0u8; 0x200];
+        data[..7].copy_from_slice(b"\x7FELF\x02\x01\x01");
+        data[16..18].copy_from_slice(&2u16.to_le_bytes());
+        data[18..20].copy_from_slice(&62u16.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[32..40].copy_from_slice(&64u64.to_le_bytes());
+        data[40..48].copy_from_slice(&0x100u64.to_le_bytes());
+        data[52..54].copy_from_slice(&64u16.to_le_bytes());
+        data[54..56].copy_from_slice(&56u16.to_le_bytes());
+        data[56..58].copy_from_slice(&1u16.to_le_bytes());
+        data[58..60].copy_from_slice(&64u16.to_le_bytes());
+        data[60..62].copy_from_slice(&3u16.to_le_bytes());
+        // segment up to 0x280, .text up to 0x300, .bss
+        data[72..80].copy_from_slice(&0x80u64.to_le_bytes());
+        data[96..104].copy_from_slice(&0x200u64.to_le_bytes());
+        data[0x140 + 4..0x140 + 8].copy_from_slice(&1u32.to_le_bytes());
+        data[0x140 + 24..0x140 + 32].copy_from_slice(&0x200u64.to_le_bytes());
+        data[0x140 + 32..0x140 + 40].copy_from_slice(&0x100u64.to_le_bytes());
+        data[0x180 + 4..0x180 + 8].copy_from_slice(&SHT_NOBITS.to_le_bytes());
+        data[0x180 + 24..0x180 + 32].copy_from_slice(&0x300u64.to_le_bytes());
+        data[0x180 + 32..0x180 + 40].copy_from_slice(&0x1000u64.to_le_bytes());
+
+        let mut elf = ELF::default();
+        elf.deserialize(&mut Cursor::new(data.as_slice())).unwrap();
+        assert!(elf.is_genuine());
+        assert_eq!(elf.size(), 0x300);
+        assert_eq!(elf.metadata()[0].1, "x86_64");
+        assert_eq!(elf.metadata()[1].1, "EXEC");
+
+        // wrong header size
+        data[52] = 52;
+        assert!(
+            ELF::default()
+                .deserialize(&mut Cursor::new(data.as_slice()))
+                .is_err()
+        );
+
+        let mut data = vec![0u8; 0x100];
+        data[..7].copy_from_slice(b"\x7FELF\x01\x02\x01");
+        data[16..18].copy_from_slice(&1u16.to_be_bytes());
+        data[18..20].copy_from_slice(&8u16.to_be_bytes());
+        data[20..24].copy_from_slice(&1u32.to_be_bytes());
+        data[32..36].copy_from_slice(&0x60u32.to_be_bytes());
+        data[40..42].copy_from_slice(&52u16.to_be_bytes());
+        data[46..48].copy_from_slice(&40u16.to_be_bytes());
+        data[48..50].copy_from_slice(&2u16.to_be_bytes());
+        data[0x88 + 16..0x88 + 20].copy_from_slice(&0x40u32.to_be_bytes());
+        data[0x88 + 20..0x88 + 24].copy_from_slice(&0x20u32.to_be_bytes());
+
+        let mut elf = ELF::default();
+        elf.deserialize(&mut Cursor::new(data.as_slice())).unwrap();
+        assert_eq!(elf.size(), 0xB0);
+        assert_eq!(elf.metadata()[0].1, "mips");
+        assert_eq!(elf.metadata()[1].1, "REL");
+
+        // a section header table past the end of the file, with its size in the first section
+        let mut data = vec![0u8; 0x40];
+        data[..7].copy_from_slice(b"\x7FELF\x02\x01\x01");
+        data[16..18].copy_from_slice(&2u16.to_le_bytes());
+        data[18..20].copy_from_slice(&62u16.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[40..48].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+        data[52..54].copy_from_slice(&64u16.to_le_bytes());
+        data[58..60].copy_from_slice(&64u16.to_le_bytes());
+        data[60..62].copy_from_slice(&0u16.to_le_bytes());
+        assert!(
+            ELF::default()
+                .deserialize(&mut Cursor::new(data.as_slice()))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::io::{Cursor, Error, ErrorKind, Read};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::{
+    audit::Metadata, carvers::fourcc_carver::FourCCCarver, deserializer::Deserializer, err,
+};
+
+// FLAC files are the fLaC marker, metadata blocks up to the one flagged as the last, then frames.
+// Frames don't give their length: a frame ends where the next one starts, and the CRC-16 at the
+// end of each frame tells which sync code is really the next frame
+// see: https://www.rfc-editor.org/rfc/rfc9639
+const MARKER: &[u8; 4] = b"fLaC";
+const STREAMINFO: u8 = 0;
+const STREAMINFO_LEN: usize = 34;
+
+// metadata block types above are reserved, or invalid
+const MAX_BLOCK_TYPE: u8 = 6;
+
+// when STREAMINFO doesn't give the maximum frame size
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+// CRC-8 of frame headers and CRC-16 of frames, both without reflection and starting from 0
+const fn crc_table(poly: u16, width: u32) -> [u16; 256] {
+    let top = 1 << (width - 1);
+    let mask = if width == 16 { 0xFFFF } else { 0xFF };
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << (width - 8);
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & top != 0 {
+                ((crc << 1) ^ poly) & mask
+            } else {
+                (crc << 1) & mask
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+const CRC8: [u16; 256] = crc_table(0x07, 8);
+const CRC16: [u16; 256] = crc_table(0x8005, 16);
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |crc, b| CRC8[(crc ^ b) as usize] as u8)
+}
+
+fn crc16(crc: u16, b: u8) -> u16 {
+    (crc << 8) ^ CRC16[((crc >> 8) as u8 ^ b) as usize]
+}
+
+// the stream parameters, in the first metadata block
+#[derive(Debug, Default, Clone, Copy)]
+struct StreamInfo {
+    max_block_size: u16,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    sample_rate: u32,
+    channels: u8,
+    bits_per_sample: u8,
+    total_samples: u64, // 0 if unknown
+}
+
+impl StreamInfo {
+    // STREAMINFO is right after the marker and the metadata block header
+    fn new(data: &[u8]) -> Option<Self> {
+        let info = data.get(8..8 + STREAMINFO_LEN)?;
+        let packed = u64::from_be_bytes(info[10..18].try_into().unwrap());
+        Some(Self {
+            max_block_size: u16::from_be_bytes([info[2], info[3]]),
+            min_frame_size: u32::from_be_bytes([0, info[4], info[5], info[6]]),
+            max_frame_size: u32::from_be_bytes([0, info[7], info[8], info[9]]),
+            sample_rate: (packed >> 44) as u32,
+            channels: ((packed >> 41) & 0x07) as u8 + 1,
+            bits_per_sample: ((packed >> 36) & 0x1F) as u8 + 1,
+            total_samples: packed & 0x0F_FFFF_FFFF,
+        })
+    }
+}
+
+// what's needed from a frame header to follow frames
+#[derive(Debug, Default, Clone, Copy)]
+struct FrameHeader {
+    variable: bool,  // the number is a sample number, and not a frame number
+    number: u64,     // frame or sample number
+    block_size: u64, // number of samples in the frame
+    len: usize,      // header length
+}
+
+impl FrameHeader {
+    fn new(data: &[u8]) -> Option<Self> {
+        let mut c = Cursor::new(data);
+        let sync = c.read_u16::<BigEndian>().ok()?;
+        if sync & 0xFFFE != 0xFFF8 {
+            return None;
+        }
+
+        let sizes = c.read_u8().ok()?;
+        let format = c.read_u8().ok()?;
+        let block_size_code = sizes >> 4;
+        let sample_rate_code = sizes & 0x0F;
+        if block_size_code == 0
+            || sample_rate_code == 0x0F
+            || format >> 4 > 10
+            || (format >> 1) & 0x07 == 3
+            || format & 1 != 0
+        {
+            return None;
+        }
+
+        // frame or sample number, coded like UTF-8
+        let first = c.read_u8().ok()?;
+        let len = first.leading_ones() as usize;
+        if len == 1 || len > 7 {
+            return None;
+        }
+        let mut number = (first & (0x7F >> len)) as u64;
+        for _ in 1..len {
+            let b = c.read_u8().ok()?;
+            if b & 0xC0 != 0x80 {
+                return None;
+            }
+            number = (number << 6) | (b & 0x3F) as u64;
+        }
+
+        let block_size = match block_size_code {
+            1 => 192,
+            2..=5 => 576 << (block_size_code - 2),
+            6 => c.read_u8().ok()? as u64 + 1,
+            7 => c.read_u16::<BigEndian>().ok()? as u64 + 1,
+            _ => 256 << (block_size_code - 8),
+        };
+        match sample_rate_code {
+            12 => {
+                c.read_u8().ok()?;
+            }
+            13 | 14 => {
+                c.read_u16::<BigEndian>().ok()?;
+            }
+            _ => (),
+        }
+
+        let len = c.position() as usize;
+        if crc8(&data[..len]) != c.read_u8().ok()? {
+            return None;
+        }
+
+        Some(Self {
+            variable: sync & 1 == 1,
+            number,
+            block_size,
+            len: len + 1,
+        })
+    }
+
+    // number of the first sample of the frame
+    fn first_sample(&self, info: &StreamInfo) -> u64 {
+        if self.variable {
+            self.number
+        } else {
+            self.number * info.max_block_size as u64
+        }
+    }
+}
+
+// a metadata block or a frame
+#[derive(Debug, Default)]
+pub struct FlacBlock {
+    block_type: Option<u8>,   // None for frames
+    last: bool,               // last metadata block, or last frame
+    info: Option<StreamInfo>, // from the STREAMINFO block, carried over to the next blocks
+}
+
+impl FlacBlock {
+    // the end of the frame at start: the start of the next frame if there's one. The CRC-16 of
+    // the frame ends it otherwise, if it's the last frame
+    fn frame_end(&mut self, data: &[u8], start: usize, header: &FrameHeader) -> Option<usize> {
+        let info = self.info?;
+        let next_sample = header.first_sample(&info) + header.block_size;
+        self.last = info.total_samples != 0 && next_sample >= info.total_samples;
+
+        let max_size = match info.max_frame_size {
+            0 => MAX_FRAME_SIZE,
+            size => size as usize,
+        };
+        let min_size = (info.min_frame_size as usize).max(header.len + 3);
+        let end = (start + max_size).min(data.len());
+
+        let mut crc = 0u16;
+        let mut last_frame = None;
+        for (pos, b) in data[start..end].iter().enumerate() {
+            crc = crc16(crc, *b);
+            let len = pos + 1;
+            if crc != 0 || len < min_size {
+                continue;
+            }
+
+            if self.last {
+                return Some(start + len);
+            }
+            last_frame.get_or_insert(start + len);
+
+            if let Some(next) = FrameHeader::new(&data[start + len..])
+                && next.variable == header.variable
+                && next.first_sample(&info) == next_sample
+            {
+                return Some(start + len);
+            }
+        }
+
+        // without the number of samples, the last frame is the one not followed by another
+        if info.total_samples == 0 && last_frame.is_some() {
+            self.last = true;
+            return last_frame;
+        }
+        None
+    }
+}
+
+impl Deserializer for FlacBlock {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let start = buffer.position() as usize;
+        let data: &[u8] = buffer.get_ref();
+
+        // frame
+        if let Some(header) = data.get(start..).and_then(FrameHeader::new) {
+            let Some(end) = self.frame_end(data, start, &header) else {
+                return err!(ErrorKind::InvalidData);
+            };
+            buffer.set_position(end as u64);
+            return Ok(end - start);
+        }
+
+        // metadata block: the first one is STREAMINFO
+        let flags = buffer.read_u8()?;
+        let block_type = flags & 0x7F;
+        let len = buffer.read_u24::<BigEndian>()? as usize;
+        if block_type > MAX_BLOCK_TYPE
+            || (start == MARKER.len()) != (block_type == STREAMINFO)
+            || (block_type == STREAMINFO && len != STREAMINFO_LEN)
+        {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        self.block_type = Some(block_type);
+        self.last = flags & 0x80 != 0;
+        if block_type == STREAMINFO {
+            self.info = StreamInfo::new(data);
+        }
+
+        buffer.set_position((start + 4 + len) as u64);
+        Ok(4 + len)
+    }
+}
+
+impl FourCCCarver for FlacBlock {
+    fn is_end(&self) -> bool {
+        self.block_type.is_none() && self.last
+    }
+
+    fn is_frame(&self) -> bool {
+        self.block_type.is_none()
+    }
+
+    fn metadata(&self) -> Metadata {
+        let (Some(STREAMINFO), Some(info)) = (self.block_type, &self.info) else {
+            return Vec::new();
+        };
+
+        let mut metadata = vec![
+            ("sample_rate", info.sample_rate.to_string()),
+            ("channels", info.channels.to_string()),
+            ("bits_per_sample", info.bits_per_sample.to_string()),
+        ];
+        if info.total_samples != 0 && info.sample_rate != 0 {
+            metadata.push((
+                "duration",
+                format!("{:.3}", info.total_samples as f64 / info.sample_rate as f64),
+            ));
+        }
+        metadata
+    }
+
+    // look for the next frame header
+    fn resync(buffer: &[u8]) -> Option<usize> {
+        (0..buffer.len().saturating_sub(1))
+            .filter(|i| buffer[*i] == 0xFF && buffer[i + 1] & 0xFE == 0xF8)
+            .find(|i| FrameHeader::new(&buffer[*i..]).is_some())
+    }
+
+    // frames need the stream parameters
+    fn carry(&mut self, previous: &Self) {
+        self.info = previous.info;
+    }
+}
+
+// the marker, followed by STREAMINFO
+#[derive(Debug, Default)]
+pub struct FlacHeader {
+    marker: [u8; 4],
+}
+
+impl Deserializer for FlacHeader {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        buffer.read_exact(&mut self.marker)?;
+        if &self.marker != MARKER || buffer.read_u8()? & 0x7F != STREAMINFO {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        buffer.set_position(MARKER.len() as u64);
+        Ok(MARKER.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a frame header with a CRC-8, then subframes padded with zeroes and a CRC-16
+    fn frame(number: u8, block_size_code: u8, len: usize) -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xF8, (block_size_code << 4) | 0x09, 0x08, number];
+        frame.push(crc8(&frame));
+        frame.resize(len - 2, 0);
+        let crc = frame.iter().fold(0, |crc, b| crc16(crc, *b));
+        frame.extend(crc.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn flac_frames() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(b"123456789".iter().fold(0, |crc, b| crc16(crc, *b)), 0xFEE8);
+
+        // STREAMINFO: blocks of 4096 samples, 44100 Hz, 2 channels, 16 bits, 8192 + 576 samples
+        let mut flac = MARKER.to_vec();
+        flac.extend([0x80, 0, 0, 34, 0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        flac.extend([0x0A, 0xC4, 0x42, 0xF0, 0x00, 0x00, 0x22, 0x40]);
+        flac.extend([0; 16]);
+        let frames = [frame(0, 12, 50), frame(1, 12, 60), frame(2, 2, 40)];
+        flac.extend(frames.concat());
+        flac.extend([0xFF, 0xF8, 0xC9, 0x08, 0x03]);
+
+        let mut c = Cursor::new(flac.as_slice());
+        FlacHeader::default().deserialize(&mut c).unwrap();
+        let mut streaminfo = FlacBlock::default();
+        streaminfo.deserialize(&mut c).unwrap();
+        assert_eq!(streaminfo.metadata()[0].1, "44100");
+        assert_eq!(streaminfo.metadata()[3].1, "0.199");
+
+        // frames use the stream parameters of STREAMINFO, without reporting them again
+        let mut blocks = vec![streaminfo];
+        loop {
+            let mut block = FlacBlock::default();
+            block.carry(blocks.last().unwrap());
+            block.deserialize(&mut c).unwrap();
+            assert!(block.metadata().is_empty());
+            blocks.push(block);
+            if blocks.last().unwrap().is_end() {
+                break;
+            }
+        }
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(c.position() as usize, flac.len() - 5);
+
+        // no STREAMINFO
+        c.set_position(42);
+        assert!(FlacBlock::default().deserialize(&mut c).is_err());
+
+        // damaged frame
+        flac[42 + 20] ^= 1;
+        let mut c = Cursor::new(flac.as_slice());
+        c.set_position(42);
+        let mut block = FlacBlock::default();
+        block.carry(&blocks[0]);
+        assert!(block.deserialize(&mut c).is_err());
+        assert_eq!(FlacBlock::resync(&flac[43..]), Some(49));
+    }
+}
@@ -0,0 +1,187 @@
+use std::io::{Cursor, Error, ErrorKind, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::{carvers::fourcc_carver::FourCCCarver, deserializer::Deserializer, err};
+
+// see: https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+
+// first byte of each block
+const EXTENSION: u8 = 0x21;
+const IMAGE_DESCRIPTOR: u8 = 0x2C;
+const TRAILER: u8 = 0x3B;
+
+// extension labels
+const PLAIN_TEXT: u8 = 0x01;
+const GRAPHIC_CONTROL: u8 = 0xF9;
+const COMMENT: u8 = 0xFE;
+const APPLICATION: u8 = 0xFF;
+
+// skip a chain of data sub-blocks, each one starting with its size, ending with an empty one
+fn skip_sub_blocks(buffer: &mut Cursor<&[u8]>) -> std::io::Result<()> {
+    loop {
+        let size = buffer.read_u8()?;
+        if size == 0 {
+            return Ok(());
+        }
+        buffer.set_position(buffer.position() + size as u64);
+    }
+}
+
+// size of a color table given by the packed fields of a descriptor
+fn color_table_size(packed: u8) -> u64 {
+    if packed & 0x80 != 0 {
+        3 << ((packed & 0x07) + 1)
+    } else {
+        0
+    }
+}
+
+// header and logical screen descriptor
+#[derive(Debug, Default)]
+pub struct GIFHeader {
+    signature: [u8; 6], // GIF87a or GIF89a
+    width: u16,
+    height: u16,
+    packed: u8, // global color table flag, color resolution, sort flag, size of global color table
+    background: u8,
+    aspect_ratio: u8,
+}
+
+impl Deserializer for GIFHeader {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        buffer.read_exact(&mut self.signature)?;
+        if &self.signature != b"GIF87a" && &self.signature != b"GIF89a" {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        self.width = buffer.read_u16::<LittleEndian>()?;
+        self.height = buffer.read_u16::<LittleEndian>()?;
+        self.packed = buffer.read_u8()?;
+        self.background = buffer.read_u8()?;
+        self.aspect_ratio = buffer.read_u8()?;
+
+        // skip the global color table
+        let pos = buffer.position() + color_table_size(self.packed);
+        buffer.set_position(pos);
+
+        Ok(pos as usize)
+    }
+}
+
+// an extension, an image or the trailer
+#[derive(Debug, Default)]
+pub struct GIFBlock {
+    introducer: u8,
+    label: u8, // for extensions
+}
+
+impl Deserializer for GIFBlock {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let start = buffer.position();
+        self.introducer = buffer.read_u8()?;
+
+        match self.introducer {
+            EXTENSION => {
+                self.label = buffer.read_u8()?;
+                match self.label {
+                    // the graphic control extension has a fixed size
+                    GRAPHIC_CONTROL => {
+                        if buffer.read_u8()? != 4 {
+                            return err!(ErrorKind::InvalidData);
+                        }
+                        buffer.set_position(buffer.position() + 4);
+                        if buffer.read_u8()? != 0 {
+                            return err!(ErrorKind::InvalidData);
+                        }
+                    }
+                    PLAIN_TEXT | COMMENT | APPLICATION => skip_sub_blocks(buffer)?,
+                    _ => return err!(ErrorKind::InvalidData),
+                }
+            }
+            IMAGE_DESCRIPTOR => {
+                // position and size of the image, then its local color table
+                buffer.set_position(buffer.position() + 8);
+                let packed = buffer.read_u8()?;
+                buffer.set_position(buffer.position() + color_table_size(packed));
+
+                // image data: LZW minimum code size and sub-blocks
+                let code_size = buffer.read_u8()?;
+                if !(2..=8).contains(&code_size) {
+                    return err!(ErrorKind::InvalidData);
+                }
+                skip_sub_blocks(buffer)?;
+            }
+            TRAILER => (),
+            _ => return err!(ErrorKind::InvalidData),
+        }
+
+        Ok((buffer.position() - start) as usize)
+    }
+}
+
+impl FourCCCarver for GIFBlock {
+    fn is_end(&self) -> bool {
+        self.introducer == TRAILER
+    }
+
+    // each image is a frame of an animated GIF
+    fn is_frame(&self) -> bool {
+        self.introducer == IMAGE_DESCRIPTOR
+    }
+
+    // look for the next graphic control extension, which starts the frames of animations
+    fn resync(buffer: &[u8]) -> Option<usize> {
+        buffer
+            .windows(3)
+            .position(|w| w == [EXTENSION, GRAPHIC_CONTROL, 4])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    // 2 frames of 1x1 pixel, with a global color table of 2 colors, looping forever
+    const ANIMATED: [u8; 85] = hex!(
+        "47 49 46 38 39 61 01 00 01 00 80 00 00 00 00 00 FF FF FF
+         21 FF 0B 4E 45 54 53 43 41 50 45 32 2E 30 03 01 00 00 00
+         21 F9 04 00 0A 00 00 00
+         2C 00 00 00 00 01 00 01 00 00 02 02 44 01 00
+         21 F9 04 00 0A 00 00 00
+         2C 00 00 00 00 01 00 01 00 00 02 02 4C 01 00
+         3B"
+    );
+
+    #[test]
+    fn gif_blocks() {
+        let mut c = Cursor::new(ANIMATED.as_slice());
+        let mut header = GIFHeader::default();
+        header.deserialize(&mut c).unwrap();
+        assert_eq!(c.position(), 19);
+
+        let mut frames = 0;
+        loop {
+            let mut block = GIFBlock::default();
+            block.deserialize(&mut c).unwrap();
+            if block.is_frame() {
+                frames += 1;
+            }
+            if block.is_end() {
+                break;
+            }
+        }
+        assert_eq!(frames, 2);
+        assert_eq!(c.position() as usize, ANIMATED.len());
+
+        // unknown block
+        let mut c = Cursor::new(hex!("21 F9 04 00 0A 00 00 00 3A").as_slice());
+        let mut block = GIFBlock::default();
+        block.deserialize(&mut c).unwrap();
+        assert!(block.deserialize(&mut c).is_err());
+
+        assert_eq!(GIFBlock::resync(&ANIMATED[20..]), Some(18));
+    }
+}
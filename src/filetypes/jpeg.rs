@@ -1,13 +1,20 @@
 use core::fmt;
 use std::{
-    io::{BufRead, Cursor, Error, ErrorKind, Read},
-    ops::Deref,
+    io::{Cursor, Error, ErrorKind},
+    ops::{Deref, Range},
 };
 
 use byteorder::{BigEndian, ReadBytesExt};
 use log::trace;
-
-use crate::{carvers::fourcc_carver::FourCCCarver, deserializer::Deserializer, err};
+use memchr::memchr;
+
+use crate::{
+    audit::Metadata,
+    carvers::fourcc_carver::FourCCCarver,
+    deserializer::Deserializer,
+    err,
+    filetypes::tiff::{Tiff, exif_metadata, exif_thumbnail, mpf_images},
+};
 
 // common JPEG segments
 
@@ -23,18 +30,15 @@ const TIM: [u8; 2] = [0xFF, 0x01];
 // start of scan => specific processing
 const SOS: [u8; 2] = [0xFF, 0xDA];
 
-#[derive(Debug, Default)]
-pub struct PNGHeader {
-    signature: u64,
-}
+// application segments
+const APP1: [u8; 2] = [0xFF, 0xE1];
+const APP2: [u8; 2] = [0xFF, 0xE2];
 
-impl Deserializer for PNGHeader {
-    fn deserialize(&mut self, buffer: &mut std::io::Cursor<&[u8]>) -> std::io::Result<usize> {
-        self.signature = buffer.read_u64::<BigEndian>()?;
+// define number of lines
+const DNL: [u8; 2] = [0xFF, 0xDC];
 
-        Ok(8)
-    }
-}
+// define restart interval
+const DRI: [u8; 2] = [0xFF, 0xDD];
 
 // a segment type is given by just 2 bytes
 #[derive(Debug, Default)]
@@ -43,15 +47,10 @@ pub struct SegmentType([u8; 2]);
 impl SegmentType {
     // those segment have no length
     pub fn is_standalone(&self) -> bool {
-        if self.0 == SOI
+        self.0 == SOI
             || self.0 == EOI
             || self.0 == TIM
-            || (self.0[0] == 0xFF && self.0[1] >= 0xD0 && self.0[1] <= 0xD7)
-        {
-            true
-        } else {
-            false
-        }
+            || (self.0[0] == 0xFF && (0xD0..=0xD7).contains(&self.0[1]))
     }
 
     // not all arrays of 2 bytes are valid Jpeg segments
@@ -73,6 +72,13 @@ impl SegmentType {
 
         true
     }
+
+    // markers found at the beginning of a segment, outside of the entropy-coded data
+    fn starts_segment(&self) -> bool {
+        self.0[0] == 0xFF
+            && (0xC0..=0xFE).contains(&self.0[1])
+            && !(0xD0..=0xD8).contains(&self.0[1])
+    }
 }
 
 impl Deref for SegmentType {
@@ -91,8 +97,11 @@ impl PartialEq<[u8; 2]> for SegmentType {
 
 #[derive(Default)]
 pub struct JpegSegment {
-    segment_type: SegmentType, // chunk type
-    length: Option<u16>,       // length of the chunk data (big-endian) include itself
+    segment_type: SegmentType,   // chunk type
+    length: Option<u16>,         // length of the chunk data (big-endian) include itself
+    entropy_length: Option<u64>, // for SOS, length of the entropy-coded data following the header
+    embedded: Vec<Range<u64>>,   // JPEG images referenced by this segment (thumbnails, previews)
+    metadata: Metadata,          // camera details found in an EXIF segment
 }
 
 impl JpegSegment {
@@ -100,14 +109,60 @@ impl JpegSegment {
     pub fn is_standalone(&self) -> bool {
         self.segment_type.is_standalone()
     }
+
+    // EXIF (APP1) segments can hold a thumbnail, and MPF (APP2) segments list the images appended
+    // after the end of this one (e.g.: large previews of camera pictures). The TIFF structure
+    // starts at offset start of buffer
+    fn embedded_images(&self, buffer: &[u8], start: usize, payload: &[u8]) -> Vec<Range<u64>> {
+        let images = if self.segment_type == APP1 && payload.starts_with(b"Exif\0\0") {
+            Tiff::new(&buffer[start + 6..])
+                .and_then(|tiff| exif_thumbnail(&tiff))
+                .map(|thumbnail| vec![thumbnail])
+        } else if self.segment_type == APP2 && payload.starts_with(b"MPF\0") {
+            Tiff::new(&buffer[start + 4..]).and_then(|tiff| mpf_images(&tiff))
+        } else {
+            None
+        };
+        let tiff_start = start as u64 + if self.segment_type == APP1 { 6 } else { 4 };
+
+        // only keep what really looks like a JPEG
+        images
+            .unwrap_or_default()
+            .into_iter()
+            .map(|image| tiff_start + image.start..tiff_start + image.end)
+            .filter(|image| {
+                buffer
+                    .get(image.start as usize..)
+                    .is_some_and(|data| data.starts_with(&[0xFF, 0xD8, 0xFF]))
+            })
+            .collect()
+    }
+
+    // the entropy-coded data ends at the first marker which is neither a stuffed 0xFF byte nor a
+    // restart marker. Returns its length, or None if no marker is found
+    fn entropy_length(data: &[u8]) -> Option<u64> {
+        let mut pos = 0;
+
+        loop {
+            pos += memchr(0xFF, &data[pos..])?;
+
+            match data.get(pos + 1)? {
+                // stuffed byte or restart marker
+                0x00 | 0xD0..=0xD7 => pos += 2,
+
+                // next marker, maybe preceded by fill bytes
+                _ => return Some(pos as u64),
+            }
+        }
+    }
 }
 
 impl fmt::Debug for JpegSegment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "segment_type={:X?} length={:?} ",
-            self.segment_type, self.length
+            "segment_type={:X?} length={:?} entropy_length={:?} embedded={:X?} ",
+            self.segment_type, self.length, self.entropy_length, self.embedded
         )
     }
 }
@@ -122,8 +177,11 @@ impl Deserializer for JpegSegment {
             return err!(ErrorKind::InvalidData);
         }
 
-        // now we can read the second byte
+        // now we can read the second byte, skipping the fill bytes which can precede any marker
         self.segment_type.0[1] = buffer.read_u8()?;
+        while self.segment_type[1] == 0xFF {
+            self.segment_type.0[1] = buffer.read_u8()?;
+        }
 
         // those markers have no length, so return
         if self.is_standalone() {
@@ -136,45 +194,56 @@ impl Deserializer for JpegSegment {
         }
 
         // all JPEG markers have the second byte > 0xC0 except for 0x01 (TIM) which is already processed
-        if self.segment_type[1] < 0xC0 {
+        if !self.segment_type.is_valid() {
             return err!(ErrorKind::InvalidData);
         }
 
-        // SOS entails a specific processing
-        // to find the next segment after the SOS, you must keep reading until you find a 0xFF bytes which
-        // is not immediately followed by 0x00 (see "byte stuffing")
-        if self.segment_type == SOS {
-            loop {
-                let mut buf = vec![];
-
-                // read until we find 0xFF
-                let _ = buffer.read_until(0xFF, &mut buf)?;
-
-                // found FF: if byte2 is a restart marker (D0 to D7) or 0, continue
-                let byte2 = buffer.read_u8()?;
-                if (0xD0 <= byte2 && byte2 <= 0xD7) || byte2 == 0 {
-                    continue;
-                } else {
-                    // rewind of 2 bytes
-                    let pos = buffer.position() - 2;
-                    buffer.set_position(pos);
-                    self.length = Some(pos as u16);
-                    return Ok(pos as usize);
-                }
-            }
+        // if we reached here, this mean it's a segment with a length, which includes the length
+        // field itself
+        let length = buffer.read_u16::<BigEndian>()?;
+        if length < 2 {
+            return err!(ErrorKind::InvalidData);
         }
+        self.length = Some(length);
 
-        // if we reached here, this mean it's a segment with a length
+        // DNL and DRI have a fixed length
+        if (self.segment_type == DNL || self.segment_type == DRI) && length != 4 {
+            return err!(ErrorKind::InvalidData);
+        }
 
-        // get length
-        self.length = Some(buffer.read_u16::<BigEndian>()?);
+        // look for nested JPEG images and camera details
+        let start = buffer.position() as usize;
+        if let Some(payload) = buffer.get_ref().get(start..start + length as usize - 2) {
+            self.embedded = self.embedded_images(buffer.get_ref(), start, payload);
+
+            if self.segment_type == APP1
+                && let Some(tiff) = payload.strip_prefix(b"Exif\0\0").and_then(Tiff::new)
+            {
+                self.metadata = exif_metadata(&tiff);
+            }
+        }
 
         // skip payload
-        let pos = buffer.position();
-        let new_pos = pos + self.length.unwrap() as u64 - 2;
-        buffer.set_position(new_pos);
+        let pos = buffer.position() + length as u64 - 2;
+        buffer.set_position(pos);
 
-        Ok(self.length.unwrap() as usize - 2)
+        // SOS entails a specific processing
+        // the scan header is followed by the entropy-coded data. To find the next segment, you
+        // must keep reading until you find a 0xFF bytes which is not immediately followed by 0x00
+        // (see "byte stuffing") or by a restart marker. A JPEG can have several scans, each one
+        // starting with its own SOS
+        if self.segment_type == SOS {
+            let data = buffer.get_ref().get(pos as usize..).unwrap_or_default();
+            let Some(entropy_length) = Self::entropy_length(data) else {
+                return err!(ErrorKind::UnexpectedEof);
+            };
+            self.entropy_length = Some(entropy_length);
+            buffer.set_position(pos + entropy_length);
+
+            return Ok(length as usize - 2 + entropy_length as usize);
+        }
+
+        Ok(length as usize - 2)
     }
 }
 
@@ -182,17 +251,34 @@ impl FourCCCarver for JpegSegment {
     fn is_end(&self) -> bool {
         self.segment_type == EOI
     }
+
+    fn embedded(&self) -> Vec<Range<u64>> {
+        self.embedded.clone()
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+
+    // look for the next marker starting a segment
+    fn resync(buffer: &[u8]) -> Option<usize> {
+        buffer
+            .windows(2)
+            .position(|w| SegmentType([w[0], w[1]]).starts_segment())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
-        fs::{self, File},
-        io::Cursor,
+        fs::File,
+        io::{Cursor, Read},
     };
 
     use hex_literal::hex;
 
+    use crate::filetypes::corpus::Corpus;
+
     use super::*;
 
     #[test]
@@ -210,6 +296,16 @@ mod tests {
         assert!(!st.is_standalone());
     }
 
+    #[test]
+    fn resync() {
+        // entropy-coded data with a restart marker, then a DQT segment
+        let raw_data = hex!("12 FF 00 34 FF D3 56 FF DB 00 43");
+        assert_eq!(JpegSegment::resync(raw_data.as_slice()), Some(7));
+
+        let raw_data = hex!("12 FF 00 34 FF D3 56 FF");
+        assert_eq!(JpegSegment::resync(raw_data.as_slice()), None);
+    }
+
     #[test]
     fn jpeg_segment() {
         let raw_data = hex!("FF E0 00 10 4A 46 49 46 00 01 01 00 00 01 00 01 00 00");
@@ -220,6 +316,103 @@ mod tests {
 
         assert_eq!(segment.segment_type, [0xFF, 0xE0]);
         assert_eq!(segment.length.unwrap(), 16);
+
+        // a length can't be lower than the length field
+        let raw_data = hex!("FF E0 00 01 4A 46 49 46");
+        let mut c = Cursor::new(raw_data.as_slice());
+        let mut segment = JpegSegment::default();
+        assert!(segment.deserialize(&mut c).is_err());
+
+        // DRI, preceded by fill bytes
+        let raw_data = hex!("FF FF FF DD 00 04 00 10 FF D9");
+        let mut c = Cursor::new(raw_data.as_slice());
+        let mut segment = JpegSegment::default();
+        segment.deserialize(&mut c).unwrap();
+        assert_eq!(segment.segment_type, DRI);
+        assert_eq!(c.position(), 8);
+    }
+
+    #[test]
+    fn sos_segment() {
+        // scan header, entropy-coded data with a stuffed byte, a restart marker and fill bytes,
+        // then another scan
+        let raw_data = hex!(
+            "FF DA 00 08 01 01 00 00 3F 00 12 FF 00 34 FF D0 56 FF FF DA 00 08 01 01 00 00 3F 00 78 FF D9"
+        );
+        let mut c = Cursor::new(raw_data.as_slice());
+
+        let mut segment = JpegSegment::default();
+        segment.deserialize(&mut c).unwrap();
+        assert_eq!(segment.length.unwrap(), 8);
+        assert_eq!(segment.entropy_length.unwrap(), 7);
+        assert_eq!(c.position(), 17);
+
+        let mut segment = JpegSegment::default();
+        segment.deserialize(&mut c).unwrap();
+        assert_eq!(segment.segment_type, SOS);
+        assert_eq!(segment.entropy_length.unwrap(), 1);
+
+        let mut segment = JpegSegment::default();
+        segment.deserialize(&mut c).unwrap();
+        assert!(segment.is_end());
+
+        // entropy-coded data longer than 64 KiB
+        let mut raw_data = hex!("FF DA 00 08 01 01 00 00 3F 00").to_vec();
+        raw_data.extend(std::iter::repeat_n(0x55, 100_000));
+        raw_data.extend(hex!("FF D9"));
+        let mut c = Cursor::new(raw_data.as_slice());
+        let mut segment = JpegSegment::default();
+        segment.deserialize(&mut c).unwrap();
+        assert_eq!(segment.entropy_length.unwrap(), 100_000);
+        assert_eq!(c.position(), 100_010);
+
+        // no marker after the entropy-coded data
+        let raw_data = hex!("FF DA 00 08 01 01 00 00 3F 00 12 34");
+        let mut c = Cursor::new(raw_data.as_slice());
+        let mut segment = JpegSegment::default();
+        assert!(segment.deserialize(&mut c).is_err());
+    }
+
+    #[test]
+    fn mpf_segment() {
+        // MPF segment listing a second image appended after the end of the first one
+        let raw_data = hex!(
+            "FF E2 00 40 4D 50 46 00 4D 4D 00 2A 00 00 00 08 00 01 B0 02 00 07 00 00
+             00 20 00 00 00 1A 00 00 00 00 20 03 00 00 00 00 00 00 00 00 00 00 00 00
+             00 00 00 01 00 02 00 00 00 04 00 00 00 3C 00 00 00 00 FF D9 FF D8 FF D9"
+        );
+        let mut c = Cursor::new(raw_data.as_slice());
+        let mut segment = JpegSegment::default();
+        segment.deserialize(&mut c).unwrap();
+        assert_eq!(c.position(), 66);
+        assert_eq!(segment.embedded().len(), 1);
+        assert_eq!(segment.embedded()[0], 68..72);
+
+        // the image referenced is not a JPEG
+        let mut raw_data = raw_data;
+        raw_data[69] = 0x00;
+        let mut c = Cursor::new(raw_data.as_slice());
+        let mut segment = JpegSegment::default();
+        segment.deserialize(&mut c).unwrap();
+        assert!(segment.embedded().is_empty());
+
+        // a JPEG with an appended image, which is ignored if it ends past the maximum size
+        let mut jpeg = hex!("FF D8").to_vec();
+        jpeg.extend(raw_data);
+        jpeg[71] = 0xD8;
+        let corpus = Corpus::new(0);
+        let ft = corpus.iter().find(|ft| ft.ext == "jpg").unwrap();
+        let result = ft.carve(&jpeg).unwrap();
+        assert_eq!((result.length, result.embedded.len()), (74, 1));
+
+        let mut corpus = Corpus::new(0);
+        corpus
+            .set_max_sizes(None, &[(String::from("jpg"), 72)])
+            .unwrap();
+        let ft = corpus.iter().find(|ft| ft.ext == "jpg").unwrap();
+        let result = ft.carve(&jpeg).unwrap();
+        assert_eq!((result.length, result.truncated), (70, false));
+        assert!(result.embedded.is_empty());
     }
 
     #[test]
@@ -238,5 +431,6 @@ mod tests {
                 break;
             }
         }
+        assert_eq!(cursor.position() as usize, buffer.len());
     }
 }
@@ -0,0 +1,691 @@
+// decodes the entropy-coded data of a carved JPEG, to check whether it renders
+// only Huffman decoding is done: coefficients are not dequantized nor transformed, as a damaged
+// fragment is detected when decoding the Huffman codes
+// see: https://www.w3.org/Graphics/JPEG/itu-t81.pdf
+use crate::{audit::Metadata, validation::Validation};
+
+// why decoding stopped
+#[derive(Debug, PartialEq)]
+enum DecodeError {
+    Truncated,   // no more data
+    Corrupt,     // data can't be decoded
+    Unsupported, // arithmetic coding, lossless or hierarchical JPEG
+}
+
+type DecodeResult<T> = Result<T, DecodeError>;
+
+// larger frames aren't decoded, as progressive ones need memory for each block
+const MAX_PIXELS: usize = 1 << 28;
+
+// a Huffman table, as described in annex F.2.2.3
+#[derive(Debug, Clone)]
+struct HuffmanTable {
+    max_code: [i32; 17],  // largest code of each length, -1 if none
+    val_ptr: [usize; 17], // index of the first value of each length
+    min_code: [i32; 17],  // smallest code of each length
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    // build the table from the number of codes of each length and the values
+    fn new(counts: &[u8], values: &[u8]) -> DecodeResult<Self> {
+        let mut table = Self {
+            max_code: [-1; 17],
+            val_ptr: [0; 17],
+            min_code: [0; 17],
+            values: values.to_vec(),
+        };
+
+        let mut code = 0i32;
+        let mut k = 0usize;
+        for len in 1..=16 {
+            let count = counts[len - 1] as usize;
+            if count > 0 {
+                table.val_ptr[len] = k;
+                table.min_code[len] = code;
+                code += count as i32;
+                k += count;
+                table.max_code[len] = code - 1;
+
+                // codes of this length must fit
+                if code > 1 << len {
+                    return Err(DecodeError::Corrupt);
+                }
+            }
+            code <<= 1;
+        }
+
+        Ok(table)
+    }
+}
+
+// a component from the frame header
+#[derive(Debug, Default, Clone)]
+struct Component {
+    id: u8,
+    h: usize,          // horizontal sampling factor
+    v: usize,          // vertical sampling factor
+    blocks_w: usize,   // number of blocks per line, padded to the MCU
+    blocks_h: usize,   // number of block lines, padded to the MCU
+    nonzero: Vec<u64>, // for progressive JPEGs, one bit for each non-zero coefficient of the blocks
+}
+
+// reads the entropy-coded data bit by bit, removing stuffed bytes
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    nb_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self {
+            data,
+            pos,
+            bits: 0,
+            nb_bits: 0,
+        }
+    }
+
+    fn bit(&mut self) -> DecodeResult<u32> {
+        if self.nb_bits == 0 {
+            let byte = *self.data.get(self.pos).ok_or(DecodeError::Truncated)?;
+            if byte == 0xFF {
+                // a 0xFF data byte is followed by 0x00, otherwise it's a marker which must not
+                // be found in the middle of a MCU
+                match self.data.get(self.pos + 1) {
+                    Some(0) => self.pos += 2,
+                    Some(_) => return Err(DecodeError::Corrupt),
+                    None => return Err(DecodeError::Truncated),
+                }
+            } else {
+                self.pos += 1;
+            }
+            self.bits = byte as u32;
+            self.nb_bits = 8;
+        }
+
+        self.nb_bits -= 1;
+        Ok((self.bits >> self.nb_bits) & 1)
+    }
+
+    fn bits(&mut self, n: u8) -> DecodeResult<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.bit()?;
+        }
+        Ok(value)
+    }
+
+    // decode a Huffman code, as described in annex F.2.2.3
+    fn decode(&mut self, table: &HuffmanTable) -> DecodeResult<u8> {
+        let mut code = 0i32;
+        for len in 1..=16 {
+            code = (code << 1) | self.bit()? as i32;
+            if code <= table.max_code[len] {
+                let index = table.val_ptr[len] + (code - table.min_code[len]) as usize;
+                return table.values.get(index).copied().ok_or(DecodeError::Corrupt);
+            }
+        }
+        Err(DecodeError::Corrupt)
+    }
+
+    // remaining bits of the current byte are padding
+    fn align(&mut self) {
+        self.nb_bits = 0;
+    }
+}
+
+// state of the decoder while walking the segments
+#[derive(Debug, Default)]
+struct Decoder {
+    dc_tables: [Option<HuffmanTable>; 4],
+    ac_tables: [Option<HuffmanTable>; 4],
+    components: Vec<Component>,
+    mcus_x: usize,
+    mcus_y: usize,
+    progressive: bool,
+    restart_interval: usize,
+    eob_run: u32,
+}
+
+// a scan header
+#[derive(Debug)]
+struct Scan {
+    components: Vec<(usize, usize, usize)>, // index of the component, DC and AC tables
+    ss: usize,                              // start of spectral selection
+    se: usize,                              // end of spectral selection
+    ah: u8,                                 // successive approximation bit position high
+}
+
+// how far a scan was decoded
+#[derive(Debug, Default)]
+struct Progress {
+    units: usize,        // number of MCUs decoded
+    total: usize,        // number of MCUs in the scan
+    error_offset: usize, // offset of the first MCU which couldn't be decoded
+}
+
+impl Decoder {
+    // define Huffman tables
+    fn dht(&mut self, mut data: &[u8]) -> DecodeResult<()> {
+        while !data.is_empty() {
+            let (class, id) = (data[0] >> 4, (data[0] & 0x0F) as usize);
+            let counts = data.get(1..17).ok_or(DecodeError::Corrupt)?;
+            let nb_values = counts.iter().map(|c| *c as usize).sum::<usize>();
+            let values = data.get(17..17 + nb_values).ok_or(DecodeError::Corrupt)?;
+
+            let table = Some(HuffmanTable::new(counts, values)?);
+            match (class, id) {
+                (0, 0..=3) => self.dc_tables[id] = table,
+                (1, 0..=3) => self.ac_tables[id] = table,
+                _ => return Err(DecodeError::Corrupt),
+            }
+
+            data = &data[17 + nb_values..];
+        }
+        Ok(())
+    }
+
+    // start of frame
+    fn sof(&mut self, data: &[u8]) -> DecodeResult<()> {
+        let (Some(height), Some(width), Some(nb)) = (
+            data.get(1..3)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize),
+            data.get(3..5)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize),
+            data.get(5).map(|b| *b as usize),
+        ) else {
+            return Err(DecodeError::Corrupt);
+        };
+
+        // height defined later by a DNL segment
+        if height == 0 {
+            return Err(DecodeError::Unsupported);
+        }
+        // a single frame is allowed, and progressive ones have up to 4 components
+        if !self.components.is_empty()
+            || width == 0
+            || nb == 0
+            || (self.progressive && nb > 4)
+            || data.len() < 6 + 3 * nb
+        {
+            return Err(DecodeError::Corrupt);
+        }
+        if width * height > MAX_PIXELS {
+            return Err(DecodeError::Unsupported);
+        }
+
+        for i in 0..nb {
+            let c = &data[6 + 3 * i..9 + 3 * i];
+            let (h, v) = ((c[1] >> 4) as usize, (c[1] & 0x0F) as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) {
+                return Err(DecodeError::Corrupt);
+            }
+            self.components.push(Component {
+                id: c[0],
+                h,
+                v,
+                ..Default::default()
+            });
+        }
+
+        let h_max = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v_max = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        self.mcus_x = width.div_ceil(8 * h_max);
+        self.mcus_y = height.div_ceil(8 * v_max);
+
+        // number of blocks of each component, as used by non-interleaved scans
+        for c in self.components.iter_mut() {
+            c.blocks_w = (width * c.h).div_ceil(h_max).div_ceil(8);
+            c.blocks_h = (height * c.v).div_ceil(v_max).div_ceil(8);
+        }
+
+        // refinement scans need to know which coefficients are already non-zero
+        if self.progressive {
+            for c in self.components.iter_mut() {
+                c.nonzero = vec![0; self.mcus_x * c.h * self.mcus_y * c.v];
+            }
+        }
+
+        Ok(())
+    }
+
+    // start of scan header
+    fn sos(&self, data: &[u8]) -> DecodeResult<Scan> {
+        let nb = *data.first().ok_or(DecodeError::Corrupt)? as usize;
+        if nb == 0 || nb > 4 || data.len() < 4 + 2 * nb {
+            return Err(DecodeError::Corrupt);
+        }
+
+        let mut components = Vec::new();
+        for i in 0..nb {
+            let (id, tables) = (data[1 + 2 * i], data[2 + 2 * i]);
+            let index = self
+                .components
+                .iter()
+                .position(|c| c.id == id)
+                .ok_or(DecodeError::Corrupt)?;
+            components.push((
+                index,
+                (tables >> 4) as usize & 3,
+                (tables & 0x0F) as usize & 3,
+            ));
+        }
+
+        let (ss, se, a) = (
+            data[1 + 2 * nb] as usize,
+            data[2 + 2 * nb] as usize,
+            data[3 + 2 * nb],
+        );
+        let scan = Scan {
+            components,
+            ss,
+            se,
+            ah: a >> 4,
+        };
+
+        // spectral selection must be consistent, and AC scans are not interleaved
+        let valid = if self.progressive {
+            (ss == 0 && se == 0) || (ss > 0 && ss <= se && se < 64 && nb == 1)
+        } else {
+            ss == 0 && se == 63
+        };
+        if !valid {
+            return Err(DecodeError::Corrupt);
+        }
+
+        Ok(scan)
+    }
+
+    // decode all the MCUs of a scan starting at pos
+    fn decode_scan(
+        &mut self,
+        data: &[u8],
+        pos: usize,
+        scan: &Scan,
+    ) -> (Progress, DecodeResult<usize>) {
+        // a non-interleaved scan is made of the blocks of its component
+        let (units_x, units_y) = if scan.components.len() == 1 {
+            let c = &self.components[scan.components[0].0];
+            (c.blocks_w, c.blocks_h)
+        } else {
+            (self.mcus_x, self.mcus_y)
+        };
+
+        let mut progress = Progress {
+            total: units_x * units_y,
+            error_offset: pos,
+            ..Default::default()
+        };
+        let mut reader = BitReader::new(data, pos);
+        let mut restart = 0u8;
+        self.eob_run = 0;
+
+        for unit in 0..progress.total {
+            // restart marker expected every restart interval
+            if self.restart_interval > 0 && unit > 0 && unit % self.restart_interval == 0 {
+                reader.align();
+                if data.get(reader.pos..reader.pos + 2) != Some(&[0xFF, 0xD0 + restart]) {
+                    return (progress, Err(DecodeError::Corrupt));
+                }
+                reader.pos += 2;
+                restart = (restart + 1) % 8;
+                self.eob_run = 0;
+            }
+
+            progress.error_offset = reader.pos;
+            if let Err(e) = self.decode_unit(&mut reader, scan, unit % units_x, unit / units_x) {
+                return (progress, Err(e));
+            }
+            progress.units += 1;
+        }
+
+        reader.align();
+        (progress, Ok(reader.pos))
+    }
+
+    // decode the blocks of a MCU
+    fn decode_unit(
+        &mut self,
+        reader: &mut BitReader,
+        scan: &Scan,
+        x: usize,
+        y: usize,
+    ) -> DecodeResult<()> {
+        let interleaved = scan.components.len() > 1;
+
+        for (index, dc, ac) in scan.components.iter() {
+            let (h, v) = if interleaved {
+                (self.components[*index].h, self.components[*index].v)
+            } else {
+                (1, 1)
+            };
+
+            for by in 0..v {
+                for bx in 0..h {
+                    let block = if interleaved {
+                        let c = &self.components[*index];
+                        (y * v + by) * self.mcus_x * c.h + x * h + bx
+                    } else {
+                        y * self.mcus_x * self.components[*index].h + x
+                    };
+                    self.decode_block(reader, scan, *index, block, *dc, *ac)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // decode a 8x8 block of coefficients
+    fn decode_block(
+        &mut self,
+        reader: &mut BitReader,
+        scan: &Scan,
+        index: usize,
+        block: usize,
+        dc: usize,
+        ac: usize,
+    ) -> DecodeResult<()> {
+        // DC coefficient
+        if scan.ss == 0 {
+            if scan.ah > 0 {
+                // refinement: one bit per block
+                reader.bit()?;
+            } else {
+                let table = self.dc_tables[dc].as_ref().ok_or(DecodeError::Corrupt)?;
+                let size = reader.decode(table)?;
+                if size > 16 {
+                    return Err(DecodeError::Corrupt);
+                }
+                reader.bits(size)?;
+            }
+
+            if self.progressive {
+                return Ok(());
+            }
+        }
+
+        let table = self.ac_tables[ac].as_ref().ok_or(DecodeError::Corrupt)?;
+        let (ss, se) = (scan.ss.max(1), scan.se);
+
+        if !self.progressive {
+            // baseline: all AC coefficients
+            let mut k = 1;
+            while k < 64 {
+                let rs = reader.decode(table)?;
+                let (r, s) = ((rs >> 4) as usize, rs & 0x0F);
+                if s == 0 {
+                    if r != 15 {
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += r;
+                if k > 63 {
+                    return Err(DecodeError::Corrupt);
+                }
+                reader.bits(s)?;
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        let flags = self.components[index]
+            .nonzero
+            .get_mut(block)
+            .ok_or(DecodeError::Corrupt)?;
+
+        if scan.ah == 0 {
+            // progressive, first AC scan: a band of coefficients, with runs of blocks ending early
+            if self.eob_run > 0 {
+                self.eob_run -= 1;
+                return Ok(());
+            }
+
+            let mut k = ss;
+            while k <= se {
+                let rs = reader.decode(table)?;
+                let (r, s) = ((rs >> 4) as usize, rs & 0x0F);
+                if s == 0 {
+                    if r < 15 {
+                        self.eob_run = (1 << r) - 1 + reader.bits(r as u8)?;
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += r;
+                if k > se {
+                    return Err(DecodeError::Corrupt);
+                }
+                reader.bits(s)?;
+                *flags |= 1 << k;
+                k += 1;
+            }
+        } else {
+            // progressive, AC refinement: one correction bit for each non-zero coefficient, and
+            // new coefficients of magnitude 1
+            let mut k = ss;
+            if self.eob_run == 0 {
+                while k <= se {
+                    let rs = reader.decode(table)?;
+                    let (mut r, s) = ((rs >> 4) as i32, rs & 0x0F);
+                    match s {
+                        0 if r < 15 => {
+                            self.eob_run = (1 << r) + reader.bits(r as u8)?;
+                            break;
+                        }
+                        0 => (),
+                        1 => {
+                            reader.bit()?;
+                        }
+                        _ => return Err(DecodeError::Corrupt),
+                    }
+
+                    // skip r zero coefficients, refining the non-zero ones on the way
+                    while k <= se {
+                        if *flags & (1 << k) != 0 {
+                            reader.bit()?;
+                        } else {
+                            if r == 0 {
+                                break;
+                            }
+                            r -= 1;
+                        }
+                        k += 1;
+                    }
+
+                    if s != 0 {
+                        if k > se {
+                            return Err(DecodeError::Corrupt);
+                        }
+                        *flags |= 1 << k;
+                    }
+                    k += 1;
+                }
+            }
+
+            // rest of the band: only refine
+            if self.eob_run > 0 {
+                while k <= se {
+                    if *flags & (1 << k) != 0 {
+                        reader.bit()?;
+                    }
+                    k += 1;
+                }
+                self.eob_run -= 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// decode the JPEG payload, segment after segment. Returns the progress of the scan being
+// decoded when an error is met
+fn decode(payload: &[u8]) -> (Progress, DecodeResult<()>) {
+    let mut decoder = Decoder::default();
+    let mut progress = Progress::default();
+
+    if !payload.starts_with(&[0xFF, 0xD8]) {
+        return (progress, Err(DecodeError::Corrupt));
+    }
+    let mut pos = 2;
+
+    loop {
+        progress.error_offset = pos;
+
+        // a marker, optionally preceded by fill bytes
+        if payload.get(pos) != Some(&0xFF) {
+            let e = if pos >= payload.len() {
+                DecodeError::Truncated
+            } else {
+                DecodeError::Corrupt
+            };
+            return (progress, Err(e));
+        }
+        while payload.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        let Some(marker) = payload.get(pos + 1).copied() else {
+            return (progress, Err(DecodeError::Truncated));
+        };
+        pos += 2;
+
+        // end of image
+        if marker == 0xD9 {
+            break;
+        }
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            continue;
+        }
+
+        // all other segments have a length
+        let Some(length) = payload
+            .get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+        else {
+            return (progress, Err(DecodeError::Truncated));
+        };
+        if length < 2 {
+            return (progress, Err(DecodeError::Corrupt));
+        }
+        let Some(data) = payload.get(pos + 2..pos + length) else {
+            return (progress, Err(DecodeError::Truncated));
+        };
+        pos += length;
+
+        let res = match marker {
+            0xC4 => decoder.dht(data),
+            0xC0 | 0xC1 => decoder.sof(data),
+            0xC2 => {
+                decoder.progressive = true;
+                decoder.sof(data)
+            }
+            // lossless, hierarchical or arithmetic coding
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCC | 0xCD..=0xCF => Err(DecodeError::Unsupported),
+            0xDD => match data {
+                [hi, lo] => {
+                    decoder.restart_interval = u16::from_be_bytes([*hi, *lo]) as usize;
+                    Ok(())
+                }
+                _ => Err(DecodeError::Corrupt),
+            },
+            0xDA => {
+                if decoder.components.is_empty() {
+                    return (progress, Err(DecodeError::Corrupt));
+                }
+                match decoder.sos(data) {
+                    Ok(scan) => {
+                        let (scan_progress, res) = decoder.decode_scan(payload, pos, &scan);
+                        progress = scan_progress;
+                        match res {
+                            Ok(end) => {
+                                pos = end;
+                                Ok(())
+                            }
+                            Err(e) => return (progress, Err(e)),
+                        }
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(e) = res {
+            return (progress, Err(e));
+        }
+    }
+
+    (progress, Ok(()))
+}
+
+// validate a carved JPEG by decoding all its scans
+pub fn validate(payload: &[u8], metadata: &mut Metadata) -> Validation {
+    let (progress, res) = decode(payload);
+
+    if progress.total > 0 {
+        metadata.push(("mcus", format!("{}/{}", progress.units, progress.total)));
+    }
+
+    match res {
+        Ok(()) => Validation::Valid,
+        Err(DecodeError::Unsupported) => Validation::Unsupported,
+        Err(e) => {
+            metadata.push(("error_offset", format!("0x{:X}", progress.error_offset)));
+            if e == DecodeError::Truncated {
+                Validation::Truncated
+            } else {
+                Validation::Corrupt
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Read};
+
+    use super::*;
+
+    #[test]
+    fn validate_jpeg() {
+        let mut f = File::open("./test/artefacts/sample.jpg").unwrap();
+        let mut jpeg = Vec::new();
+        f.read_to_end(&mut jpeg).unwrap();
+
+        let mut metadata = Metadata::new();
+        assert_eq!(validate(&jpeg, &mut metadata), Validation::Valid);
+        assert_eq!(metadata[0].0, "mcus");
+
+        // cut in the middle of the scan
+        let mut metadata = Metadata::new();
+        let truncated = &jpeg[..jpeg.len() / 2];
+        assert_eq!(validate(truncated, &mut metadata), Validation::Truncated);
+
+        // a fragment of another file spliced inside the scan
+        let mut corrupt = jpeg.clone();
+        corrupt[jpeg.len() / 2..jpeg.len() / 2 + 512].copy_from_slice(&[0xFF; 512]);
+        let mut metadata = Metadata::new();
+        assert_eq!(validate(&corrupt, &mut metadata), Validation::Corrupt);
+        assert_eq!(metadata[1].0, "error_offset");
+
+        // a second frame header
+        let sof = jpeg.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        let mut frames = jpeg.clone();
+        frames.splice(sof..sof, jpeg[sof..sof + 13].to_vec());
+        let mut metadata = Metadata::new();
+        assert_eq!(validate(&frames, &mut metadata), Validation::Corrupt);
+
+        // too many pixels to be decoded
+        let mut huge = jpeg.clone();
+        huge[sof + 5..sof + 9].fill(0xFF);
+        let mut metadata = Metadata::new();
+        assert_eq!(validate(&huge, &mut metadata), Validation::Unsupported);
+    }
+}
@@ -1,5 +1,18 @@
+pub mod bmff;
 pub mod bmp;
 pub mod corpus;
+pub mod ebml;
+pub mod elf;
+pub mod flac;
+pub mod gif;
 pub mod jpeg;
+pub mod jpeg_decoder;
+pub mod mp3;
+pub mod ogg;
+pub mod pdf;
+pub mod pe;
 pub mod png;
-pub mod wav;
+pub mod riff;
+pub mod sqlite;
+pub mod tiff;
+pub mod zip;
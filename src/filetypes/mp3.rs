@@ -0,0 +1,215 @@
+// MP3 files have no container: an optional ID3v2 tag, MPEG audio frames back to back, and an
+// optional ID3v1 tag. Each frame starts with a header giving its length
+// see: http://www.mp3-tech.org/programmer/frame_header.html and https://id3.org/id3v2.4.0-structure
+use log::debug;
+
+use crate::{carvers::CarvingResult, filetypes::corpus::FileType};
+
+// a few frames could be found anywhere by chance
+const MIN_FRAMES: usize = 10;
+
+// ID3v1 tag, at the very end
+const ID3V1_LEN: usize = 128;
+
+// kbit/s for Layer III, by bitrate index
+const MPEG1_BITRATES: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const MPEG2_BITRATES: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+// the fields of a frame header which don't change from one frame to the other
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stream {
+    version: u8, // 3 for MPEG1, 2 for MPEG2, 0 for MPEG2.5
+    sample_rate: u32,
+}
+
+// the stream and the length of the Layer III frame starting data
+fn frame(data: &[u8]) -> Option<(Stream, usize)> {
+    let header = data.get(..4)?;
+
+    // sync, and Layer III
+    if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 || (header[1] >> 1) & 0x03 != 0x01 {
+        return None;
+    }
+
+    let version = (header[1] >> 3) & 0x03;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let sample_rate_index = ((header[2] >> 2) & 0x03) as usize;
+    let padding = ((header[2] >> 1) & 0x01) as usize;
+    let emphasis = header[3] & 0x03;
+
+    // reserved values, and free format which has no length
+    if version == 1
+        || bitrate_index == 0
+        || bitrate_index == 15
+        || sample_rate_index == 3
+        || emphasis == 2
+    {
+        return None;
+    }
+
+    let (bitrate, sample_rate, factor) = match version {
+        3 => (
+            MPEG1_BITRATES[bitrate_index],
+            [44100, 48000, 32000][sample_rate_index],
+            144,
+        ),
+        2 => (
+            MPEG2_BITRATES[bitrate_index],
+            [22050, 24000, 16000][sample_rate_index],
+            72,
+        ),
+        _ => (
+            MPEG2_BITRATES[bitrate_index],
+            [11025, 12000, 8000][sample_rate_index],
+            72,
+        ),
+    };
+
+    let len = (factor * bitrate * 1000 / sample_rate) as usize + padding;
+    Some((
+        Stream {
+            version,
+            sample_rate,
+        },
+        len,
+    ))
+}
+
+// length of the ID3v2 tag at the start of data, if any. Its size is a syncsafe integer
+fn id3v2_len(data: &[u8]) -> Option<usize> {
+    let header = data.get(..10)?;
+    if &header[..3] != b"ID3"
+        || !(2..=4).contains(&header[3])
+        || header[4] == 0xFF
+        || header[6..].iter().any(|b| b & 0x80 != 0)
+    {
+        return None;
+    }
+
+    let size = header[6..]
+        .iter()
+        .fold(0usize, |size, b| (size << 7) | *b as usize);
+
+    // a footer is a copy of the header
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+pub fn carve_mp3(mmap: &[u8], ft: &FileType) -> anyhow::Result<CarvingResult> {
+    let window = &mmap[..ft.max_size.min(mmap.len())];
+
+    // either a tag or the first frame
+    let mut pos = match id3v2_len(window) {
+        Some(len) => len,
+        None if window.starts_with(b"ID3") => return Ok(CarvingResult::default()),
+        None => 0,
+    };
+
+    // follow the frames of the same stream
+    let mut stream = None;
+    let mut frames = 0usize;
+    while let Some((header, len)) = window.get(pos..).and_then(frame) {
+        if *stream.get_or_insert(header) != header {
+            break;
+        }
+
+        // cut by the maximum size for this file type
+        if pos + len > window.len() {
+            if window.len() == ft.max_size && frames >= MIN_FRAMES {
+                debug!(
+                    "file type {}: offset {} is over the maximum size {}",
+                    &ft.ext,
+                    pos + len,
+                    ft.max_size
+                );
+                return Ok(CarvingResult::truncated(window.len()));
+            }
+            break;
+        }
+
+        pos += len;
+        frames += 1;
+    }
+
+    if frames < MIN_FRAMES {
+        return Ok(CarvingResult::default());
+    }
+
+    if window.get(pos..pos + 3) == Some(b"TAG") && pos + ID3V1_LEN <= window.len() {
+        pos += ID3V1_LEN;
+    }
+
+    if pos < ft.min_size {
+        return Ok(CarvingResult::default());
+    }
+
+    let mut result = CarvingResult::new(pos as u64, pos);
+    result.metadata.push(("frames", frames.to_string()));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::filetypes::corpus::Corpus;
+
+    // frames of 417 bytes: MPEG1 Layer III, 128 kbit/s, 44100 Hz
+    fn frames(nb_frames: usize) -> Vec<u8> {
+        let mut data = vec![0u8; 417 * nb_frames];
+        for frame in data.chunks_mut(417) {
+            frame[..4].copy_from_slice(&hex!("FF FB 90 44"));
+        }
+        data
+    }
+
+    #[test]
+    fn mp3_frames() {
+        // MPEG1 Layer III, 128 kbit/s, 44100 Hz, with and without padding
+        let (stream, len) = frame(&hex!("FF FB 90 44")).unwrap();
+        assert_eq!(stream.sample_rate, 44100);
+        assert_eq!(len, 417);
+        assert_eq!(frame(&hex!("FF FB 92 44")).unwrap().1, 418);
+
+        // MPEG2 Layer III, 64 kbit/s, 22050 Hz
+        assert_eq!(frame(&hex!("FF F3 80 C4")).unwrap().1, 208);
+
+        // free format, Layer II
+        assert!(frame(&hex!("FF FB 00 44")).is_none());
+        assert!(frame(&hex!("FF FD 90 44")).is_none());
+
+        // tag of 257 bytes
+        assert_eq!(id3v2_len(&hex!("49 44 33 04 00 00 00 00 02 01")), Some(267));
+        assert_eq!(id3v2_len(&hex!("49 44 33 04 00 00 00 00 02 81")), None);
+    }
+
+    #[test]
+    fn carve_frames() {
+        let corpus = Corpus::new(0);
+        let ft = corpus.iter().find(|ft| ft.ext == "mp3").unwrap();
+
+        // frames, followed by something else
+        let mut data = frames(10);
+        data.resize(5000, 0);
+        let result = carve_mp3(&data, ft).unwrap();
+        assert_eq!(result.length, 4170);
+        assert_eq!(result.metadata, [("frames", String::from("10"))]);
+
+        // the ID3v1 tag is part of the file
+        data[4170..4173].copy_from_slice(b"TAG");
+        assert_eq!(carve_mp3(&data, ft).unwrap().length, 4170 + ID3V1_LEN);
+
+        // too few frames
+        let mut data = frames(9);
+        data.resize(5000, 0);
+        assert_eq!(carve_mp3(&data, ft).unwrap().length, 0);
+
+        // another stream starts: MPEG2 Layer III, 64 kbit/s, 22050 Hz
+        let mut data = frames(12);
+        data[417 * 10..417 * 10 + 4].copy_from_slice(&hex!("FF F3 80 C4"));
+        assert_eq!(carve_mp3(&data, ft).unwrap().length, 4170);
+    }
+}
@@ -0,0 +1,293 @@
+use std::io::{Cursor, Error, ErrorKind, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use memchr::memmem;
+
+use crate::{
+    audit::Metadata,
+    carvers::{Subtype, fourcc_carver::FourCCCarver},
+    deserializer::Deserializer,
+    err,
+};
+
+// Ogg files are a chain of pages, each one with a header, a segment table giving its length and a
+// CRC. Pages of several logical streams can be interleaved, the first page of each stream is
+// flagged as its beginning (BOS), the last one as its end (EOS)
+// see: https://www.rfc-editor.org/rfc/rfc3533
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+const HEADER_LEN: usize = 27;
+
+// header type flags
+const CONTINUED: u8 = 0x01;
+const BOS: u8 = 0x02;
+const EOS: u8 = 0x04;
+
+// no packet ends in the page
+const NO_GRANULE: i64 = -1;
+
+pub const OPUS: Subtype = Subtype {
+    ext: "opus",
+    category: "audio/opus",
+};
+pub const OGG_FLAC: Subtype = Subtype {
+    ext: "oga",
+    category: "audio/oga",
+};
+pub const SPEEX: Subtype = Subtype {
+    ext: "spx",
+    category: "audio/spx",
+};
+pub const THEORA: Subtype = Subtype {
+    ext: "ogv",
+    category: "videos/ogv",
+};
+
+// the page CRC, without reflection and starting from 0
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04C11DB7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+const CRC: [u32; 256] = crc_table();
+
+fn crc(page: &[u8]) -> u32 {
+    page.iter().enumerate().fold(0, |crc, (i, b)| {
+        // the CRC field counts as zeroes
+        let b = if (22..26).contains(&i) { 0 } else { *b };
+        (crc << 8) ^ CRC[((crc >> 24) as u8 ^ b) as usize]
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct OggPage {
+    flags: u8,
+    granule: i64,
+    serial: u32,
+    subtype: Option<Subtype>, // codec of the first packet of a stream
+    last: bool,               // end of the last stream
+    duration: Option<f64>,    // in seconds, for the first stream if it's Vorbis or Opus
+}
+
+impl OggPage {
+    // the codec, from the first packet of a stream
+    fn codec(packet: &[u8]) -> Option<Subtype> {
+        if packet.starts_with(b"OpusHead") {
+            Some(OPUS)
+        } else if packet.starts_with(b"\x7FFLAC") {
+            Some(OGG_FLAC)
+        } else if packet.starts_with(b"Speex   ") {
+            Some(SPEEX)
+        } else if packet.starts_with(b"\x80theora") {
+            Some(THEORA)
+        } else {
+            None
+        }
+    }
+
+    // the duration of the first stream of the file, if this page ends it: the granule position
+    // counts samples for Vorbis, and samples at 48 kHz after a pre-skip for Opus
+    fn stream_duration(&self, data: &[u8]) -> Option<f64> {
+        let serial = u32::from_le_bytes(data.get(14..18)?.try_into().unwrap());
+        let segments = *data.get(26)? as usize;
+        let packet = data.get(HEADER_LEN + segments..)?;
+        if serial != self.serial || self.granule < 0 {
+            return None;
+        }
+
+        if packet.starts_with(b"\x01vorbis") {
+            let rate = u32::from_le_bytes(packet.get(12..16)?.try_into().unwrap());
+            (rate != 0).then(|| self.granule as f64 / rate as f64)
+        } else if packet.starts_with(b"OpusHead") {
+            let pre_skip = u16::from_le_bytes(packet.get(10..12)?.try_into().unwrap());
+            Some((self.granule - pre_skip as i64).max(0) as f64 / 48000.0)
+        } else {
+            None
+        }
+    }
+}
+
+impl Deserializer for OggPage {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let start = buffer.position() as usize;
+
+        let mut capture_pattern = [0u8; 4];
+        buffer.read_exact(&mut capture_pattern)?;
+        let version = buffer.read_u8()?;
+        self.flags = buffer.read_u8()?;
+        if &capture_pattern != CAPTURE_PATTERN
+            || version != 0
+            || self.flags & !(CONTINUED | BOS | EOS) != 0
+        {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        self.granule = buffer.read_i64::<LittleEndian>()?;
+        self.serial = buffer.read_u32::<LittleEndian>()?;
+        let _sequence = buffer.read_u32::<LittleEndian>()?;
+        let checksum = buffer.read_u32::<LittleEndian>()?;
+        let mut segments = vec![0u8; buffer.read_u8()? as usize];
+        buffer.read_exact(&mut segments)?;
+
+        // a page where no packet ends has no granule position
+        if !segments.is_empty()
+            && segments.iter().all(|len| *len == 255)
+            && self.granule != NO_GRANULE
+        {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        let len =
+            HEADER_LEN + segments.len() + segments.iter().map(|len| *len as usize).sum::<usize>();
+        let data = buffer.get_ref();
+        let Some(page) = data.get(start..start + len) else {
+            return err!(ErrorKind::UnexpectedEof);
+        };
+        if crc(page) != checksum {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        if self.flags & BOS != 0 {
+            self.subtype = Self::codec(&page[HEADER_LEN + segments.len()..]);
+        }
+
+        // the end of a stream ends the file, unless pages of other streams follow
+        if self.flags & EOS != 0 {
+            self.duration = self.stream_duration(data);
+            let next = data.get(start + len..start + len + 6);
+            self.last = !next.is_some_and(|next| {
+                next.starts_with(CAPTURE_PATTERN) && next[4] == 0 && next[5] & BOS == 0
+            });
+        }
+
+        buffer.set_position((start + len) as u64);
+        Ok(len)
+    }
+}
+
+impl FourCCCarver for OggPage {
+    fn is_end(&self) -> bool {
+        self.last
+    }
+
+    // Vorbis is the default
+    fn subtype(&self) -> Option<Subtype> {
+        self.subtype
+    }
+
+    fn metadata(&self) -> Metadata {
+        match self.duration {
+            Some(duration) => vec![("duration", format!("{:.3}", duration))],
+            None => Vec::new(),
+        }
+    }
+
+    // look for the next page
+    fn resync(buffer: &[u8]) -> Option<usize> {
+        memmem::find(buffer, CAPTURE_PATTERN)
+    }
+}
+
+// the first page, which begins a stream
+#[derive(Debug, Default)]
+pub struct OggHeader {
+    capture_pattern: [u8; 4],
+    version: u8,
+    flags: u8,
+}
+
+impl Deserializer for OggHeader {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        buffer.read_exact(&mut self.capture_pattern)?;
+        self.version = buffer.read_u8()?;
+        self.flags = buffer.read_u8()?;
+        if &self.capture_pattern != CAPTURE_PATTERN || self.version != 0 || self.flags != BOS {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        // the first page is read as a page, to get the codec
+        buffer.set_position(0);
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(flags: u8, granule: i64, serial: u32, packet: &[u8]) -> Vec<u8> {
+        let mut page = CAPTURE_PATTERN.to_vec();
+        page.extend([0, flags]);
+        page.extend(granule.to_le_bytes());
+        page.extend(serial.to_le_bytes());
+        page.extend([0; 8]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend(packet);
+        let crc = crc(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    fn walk(data: &[u8]) -> Vec<OggPage> {
+        let mut c = Cursor::new(data);
+        let mut pages = Vec::new();
+        loop {
+            let mut page = OggPage::default();
+            page.deserialize(&mut c).unwrap();
+            let last = page.is_end();
+            pages.push(page);
+            if last {
+                return pages;
+            }
+        }
+    }
+
+    #[test]
+    fn ogg_pages() {
+        // Opus with a pre-skip of 312, then 2 s at 48 kHz
+        let mut opus_head = b"OpusHead\x01\x02".to_vec();
+        opus_head.extend(312u16.to_le_bytes());
+        opus_head.extend([0; 7]);
+        let mut ogg = page(BOS, 0, 7, &opus_head);
+        ogg.extend(page(0, 0, 7, b"OpusTags"));
+        ogg.extend(page(0, 48000, 7, &[1; 100]));
+        ogg.extend(page(EOS, 96312, 7, &[2; 100]));
+        let len = ogg.len();
+        ogg.extend(page(BOS, 0, 8, b"\x01vorbis"));
+
+        let pages = walk(&ogg);
+        assert_eq!(pages.len(), 4);
+        assert_eq!(pages[0].subtype(), Some(OPUS));
+        assert_eq!(pages[3].metadata()[0].1, "2.000");
+
+        // damaged page
+        let mut damaged = ogg[..len].to_vec();
+        damaged[len - 10] ^= 1;
+        let mut c = Cursor::new(damaged.as_slice());
+        c.set_position((len - 128) as u64);
+        assert!(OggPage::default().deserialize(&mut c).is_err());
+
+        // interleaved streams: the first end isn't the end of the file
+        let mut ogg = page(BOS, 0, 1, b"\x80theora");
+        ogg.extend(page(BOS, 0, 2, b"\x01vorbis"));
+        ogg.extend(page(EOS, 10, 1, &[0; 10]));
+        ogg.extend(page(EOS, 10, 2, &[0; 10]));
+        let pages = walk(&ogg);
+        assert_eq!(pages.len(), 4);
+        assert_eq!(pages[0].subtype(), Some(THEORA));
+    }
+}
@@ -0,0 +1,285 @@
+// PDF files have no size in their header. They end with %%EOF, but each incremental update
+// appends a new body, a new cross-reference section and a new %%EOF: the document ends at the last
+// %%EOF whose startxref points to a cross-reference section
+// see: https://opensource.adobe.com/dc-acrobat-sdk-docs/pdfstandards/PDF32000_2008.pdf
+use std::ops::Range;
+
+use log::debug;
+use memchr::memmem;
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+
+use crate::{audit::Metadata, carvers::CarvingResult, filetypes::corpus::FileType};
+
+const HEADER: &[u8] = b"%PDF-";
+const EOF: &[u8] = b"%%EOF";
+const STARTXREF: &[u8] = b"startxref";
+
+// object streams are small, don't inflate anything bigger
+const MAX_OBJECT_STREAM: usize = 10_000_000;
+
+// how far back the start of a dictionary is looked for
+const MAX_DICTIONARY: usize = 64 * 1024;
+
+// the version from the header, like 1.7
+fn version(data: &[u8]) -> Option<String> {
+    let version = data.strip_prefix(HEADER)?.get(..3)?;
+    match version {
+        [major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit() => {
+            Some(String::from_utf8_lossy(version).to_string())
+        }
+        _ => None,
+    }
+}
+
+// read an integer after optional whitespaces
+fn integer(data: &[u8]) -> Option<(usize, &[u8])> {
+    let data = data.trim_ascii_start();
+    let len = data.iter().take_while(|b| b.is_ascii_digit()).count();
+    let value = std::str::from_utf8(&data[..len]).ok()?.parse().ok()?;
+    Some((value, &data[len..]))
+}
+
+// a cross-reference section is either a table, or a stream object
+fn is_xref(data: &[u8], offset: usize) -> bool {
+    let Some(data) = data.get(offset..) else {
+        return false;
+    };
+    if data.starts_with(b"xref") {
+        return true;
+    }
+
+    // object number, generation number, then obj
+    integer(data)
+        .and_then(|(_, rest)| integer(rest))
+        .is_some_and(|(_, rest)| rest.trim_ascii_start().starts_with(b"obj"))
+}
+
+// the end of the %%EOF marker at pos, with its end of line, if the startxref right before it
+// points to a cross-reference section
+fn end_of_update(data: &[u8], pos: usize) -> Option<usize> {
+    let before = &data[pos.saturating_sub(64)..pos];
+    let startxref = memmem::rfind(before, STARTXREF)?;
+    let (offset, _) = integer(&before[startxref + STARTXREF.len()..])?;
+
+    if offset >= pos || !is_xref(data, offset) {
+        return None;
+    }
+
+    let mut end = pos + EOF.len();
+    match data.get(end..end + 2) {
+        Some(b"\r\n") => end += 2,
+        Some([b'\r' | b'\n', _]) | Some([b'\r' | b'\n']) => end += 1,
+        _ if data.get(end).is_some_and(|b| *b == b'\r' || *b == b'\n') => end += 1,
+        _ => (),
+    }
+    Some(end)
+}
+
+// the dictionary around pos, between its << and >>
+fn dictionary(data: &[u8], pos: usize) -> Option<Range<usize>> {
+    let mut depth = 0;
+    let mut start = None;
+    for i in (pos.saturating_sub(MAX_DICTIONARY).max(1)..pos).rev() {
+        match &data[i - 1..=i] {
+            b">>" => depth += 1,
+            b"<<" if depth == 0 => {
+                start = Some(i - 1);
+                break;
+            }
+            b"<<" => depth -= 1,
+            _ => (),
+        }
+    }
+    let start = start?;
+
+    let mut depth = 0;
+    let mut end = data.len();
+    for i in pos..data.len().saturating_sub(1) {
+        match &data[i..i + 2] {
+            b"<<" => depth += 1,
+            b">>" if depth == 0 => {
+                end = i + 2;
+                break;
+            }
+            b">>" => depth -= 1,
+            _ => (),
+        }
+    }
+
+    Some(start..end)
+}
+
+// the largest /Count of the page tree nodes found in data
+fn page_tree_count(data: &[u8]) -> Option<usize> {
+    let mut pages = None;
+
+    for pattern in [b"/Type/Pages".as_slice(), b"/Type /Pages".as_slice()] {
+        for pos in memmem::find_iter(data, pattern) {
+            let Some(dictionary) = dictionary(data, pos) else {
+                continue;
+            };
+            let dictionary = &data[dictionary];
+            if let Some(count) = memmem::find(dictionary, b"/Count")
+                && let Some((count, _)) = integer(&dictionary[count + 6..])
+            {
+                pages = pages.max(Some(count));
+            }
+        }
+    }
+
+    pages
+}
+
+// the decompressed content of the object streams, where PDF 1.5 and later put most objects
+fn object_streams(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut streams = Vec::new();
+
+    for pattern in [b"/Type/ObjStm".as_slice(), b"/Type /ObjStm".as_slice()] {
+        for pos in memmem::find_iter(data, pattern) {
+            let Some(dictionary) = dictionary(data, pos) else {
+                continue;
+            };
+            if memmem::find(&data[dictionary.clone()], b"/FlateDecode").is_none() {
+                continue;
+            }
+
+            // the stream follows its dictionary, its length could be an indirect object so
+            // rely on endstream
+            let Some(stream) = memmem::find(&data[dictionary.end..], b"stream") else {
+                continue;
+            };
+            let mut start = dictionary.end + stream + 6;
+            if data.get(start) == Some(&b'\r') {
+                start += 1;
+            }
+            if data.get(start) == Some(&b'\n') {
+                start += 1;
+            }
+            let Some(end) = memmem::find(&data[start..], b"endstream") else {
+                continue;
+            };
+
+            if let Ok(content) =
+                decompress_to_vec_zlib_with_limit(&data[start..start + end], MAX_OBJECT_STREAM)
+            {
+                streams.push(content);
+            }
+        }
+    }
+
+    streams
+}
+
+// the number of pages is the count of the root of the page tree, which is the largest one. The
+// page tree is either in the body, or in compressed object streams
+fn pages(data: &[u8]) -> Option<usize> {
+    object_streams(data)
+        .iter()
+        .filter_map(|stream| page_tree_count(stream))
+        .chain(page_tree_count(data))
+        .max()
+}
+
+pub fn carve_pdf(mmap: &[u8], ft: &FileType) -> anyhow::Result<CarvingResult> {
+    let window = &mmap[..ft.max_size.min(mmap.len())];
+    let Some(version) = version(window) else {
+        return Ok(CarvingResult::default());
+    };
+
+    // the document can't go past the start of the next one
+    let next = memmem::find(&window[HEADER.len()..], HEADER).map(|pos| pos + HEADER.len());
+    let data = &window[..next.unwrap_or(window.len())];
+
+    // the last update which is consistent
+    let end = memmem::find_iter(data, EOF)
+        .filter_map(|pos| end_of_update(data, pos))
+        .last();
+
+    let Some(end) = end else {
+        // nothing found within the maximum size for this file type
+        if next.is_none() && window.len() == ft.max_size {
+            debug!(
+                "file type {}: no end found within the maximum size {}",
+                &ft.ext, ft.max_size
+            );
+            return Ok(CarvingResult::truncated(window.len()));
+        }
+        return Ok(CarvingResult::default());
+    };
+
+    if end < ft.min_size {
+        return Ok(CarvingResult::default());
+    }
+
+    let mut metadata = Metadata::new();
+    metadata.push(("version", version));
+    if let Some(pages) = pages(&data[..end]) {
+        metadata.push(("pages", pages.to_string()));
+    }
+
+    let mut result = CarvingResult::new(end as u64, end);
+    result.metadata = metadata;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a document with 2 pages, and an incremental update
+    const PDF: &[u8] = b"%PDF-1.4
+1 0 obj << /Type /Catalog /Pages 2 0 R >> endobj
+2 0 obj << /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 >> endobj
+3 0 obj << /Type /Page /Parent 2 0 R >> endobj
+4 0 obj << /Type /Page /Parent 2 0 R >> endobj
+xref
+0 5
+trailer << /Size 5 /Root 1 0 R >>
+startxref
+215
+%%EOF
+5 0 obj << /Title (update) >> endobj
+xref
+0 1
+trailer << /Size 6 /Root 1 0 R /Prev 215 >>
+startxref
+315
+%%EOF
+";
+
+    #[test]
+    fn pdf_updates() {
+        assert_eq!(version(PDF).unwrap(), "1.4");
+        assert_eq!(pages(PDF), Some(2));
+
+        // both updates are consistent, the second one ends the document
+        assert!(is_xref(PDF, 215));
+        assert!(is_xref(PDF, 315));
+        let first = memmem::find(PDF, EOF).unwrap();
+        assert_eq!(end_of_update(PDF, first), Some(first + 6));
+        let last = memmem::rfind(PDF, EOF).unwrap();
+        assert_eq!(end_of_update(PDF, last), Some(PDF.len()));
+
+        // startxref pointing nowhere
+        let mut pdf = PDF.to_vec();
+        pdf[last - 3] = b'0';
+        assert_eq!(end_of_update(&pdf, last), None);
+
+        // page tree in an object stream
+        let objects = b"2 0 << /Type /Pages /Kids [3 0 R] /Count 1 >>";
+        let mut pdf =
+            b"%PDF-1.5\n6 0 obj << /Type /ObjStm /Filter /FlateDecode >> stream\n".to_vec();
+        pdf.extend(miniz_oxide::deflate::compress_to_vec_zlib(objects, 6));
+        pdf.extend(b"\nendstream\nendobj\n");
+        assert_eq!(pages(&pdf), Some(1));
+
+        // no start of dictionary, or too far back
+        assert_eq!(dictionary(b"/Type /Pages /Count 3 >>", 1), None);
+        let mut far = b"<< ".to_vec();
+        far.resize(MAX_DICTIONARY + 10, b' ');
+        far.extend(b"/Type /Pages /Count 3 >>");
+        assert_eq!(dictionary(&far, MAX_DICTIONARY + 10), None);
+        assert_eq!(dictionary(&far, MAX_DICTIONARY), Some(0..far.len()));
+        assert_eq!(page_tree_count(&far), None);
+    }
+}
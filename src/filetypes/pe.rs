@@ -0,0 +1,294 @@
+// PE files (exe, dll, sys, ...) start with a DOS header pointing to the PE signature, followed by
+// the COFF header, the optional header and the section table. The raw data of the sections come
+// next, and the file may end with an overlay, like the certificate table of signed files
+// see: https://learn.microsoft.com/en-us/windows/win32/debug/pe-format
+use std::io::{Cursor, Error, ErrorKind};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{
+    audit::Metadata,
+    carvers::{Subtype, size_carver::SizeCarver},
+    deserializer::Deserializer,
+    err,
+};
+
+const SIGNATURE: &[u8; 4] = b"PE\0\0";
+const COFF_HEADER_LEN: usize = 20;
+const SECTION_HEADER_LEN: usize = 40;
+const SYMBOL_LEN: u64 = 18;
+
+// the DOS header and stub are usually much smaller
+const MAX_LFANEW: usize = 0x10000;
+
+// the loader doesn't accept more sections
+const MAX_SECTIONS: u16 = 96;
+
+// optional header magics
+const PE32: u16 = 0x10B;
+const PE32_PLUS: u16 = 0x20B;
+
+// characteristics
+const EXECUTABLE_IMAGE: u16 = 0x0002;
+const DLL: u16 = 0x2000;
+
+// the certificate table is the fifth data directory
+const SECURITY_DIRECTORY: usize = 4;
+
+pub const DLL_SUBTYPE: Subtype = Subtype {
+    ext: "dll",
+    category: "executables/dll",
+};
+
+// the name of a machine type
+fn machine(machine: u16) -> Option<&'static str> {
+    match machine {
+        0x014C => Some("i386"),
+        0x0166 => Some("mips"),
+        0x01C0 => Some("arm"),
+        0x01C2 => Some("thumb"),
+        0x01C4 => Some("armnt"),
+        0x01F0 => Some("powerpc"),
+        0x0200 => Some("ia64"),
+        0x5032 => Some("riscv32"),
+        0x5064 => Some("riscv64"),
+        0x6232 => Some("loongarch32"),
+        0x6264 => Some("loongarch64"),
+        0x8664 => Some("x86_64"),
+        0xAA64 => Some("arm64"),
+        _ => None,
+    }
+}
+
+// a UTC date from seconds since the Unix epoch
+// see: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn utc(timestamp: u32) -> String {
+    let days = timestamp as i64 / 86400;
+    let seconds = timestamp as i64 % 86400;
+
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default)]
+pub struct PE {
+    machine: u16,         // the target architecture
+    timestamp: u32,       // when the linker created the file, seconds since the Unix epoch
+    characteristics: u16, // flags, like DLL
+    size: u64,            // the end of the headers, sections, symbols and certificates
+}
+
+impl SizeCarver for PE {
+    fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    // object files have no DOS header, and images are flagged as executable
+    fn is_genuine(&self) -> bool {
+        machine(self.machine).is_some() && self.characteristics & EXECUTABLE_IMAGE != 0
+    }
+
+    fn ext(&self) -> String {
+        String::from("exe")
+    }
+
+    fn subtype(&self) -> Option<Subtype> {
+        (self.characteristics & DLL != 0).then_some(DLL_SUBTYPE)
+    }
+
+    fn metadata(&self) -> Metadata {
+        vec![
+            (
+                "machine",
+                machine(self.machine).unwrap_or("unknown").to_string(),
+            ),
+            ("timestamp", utc(self.timestamp)),
+            ("dll", (self.characteristics & DLL != 0).to_string()),
+        ]
+    }
+}
+
+impl Deserializer for PE {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let data: &[u8] = buffer.get_ref();
+        let eof = || Error::from(ErrorKind::UnexpectedEof);
+
+        // the DOS header gives the offset of the PE signature
+        let dos_header = data.get(..0x40).ok_or_else(eof)?;
+        if &dos_header[..2] != b"MZ" {
+            return err!(ErrorKind::InvalidData);
+        }
+        let lfanew = LittleEndian::read_u32(&dos_header[0x3C..]) as usize;
+        if lfanew > MAX_LFANEW {
+            return err!(ErrorKind::InvalidData);
+        }
+        if data.get(lfanew..lfanew + 4).ok_or_else(eof)? != SIGNATURE {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        let coff = lfanew + 4;
+        let header = data.get(coff..coff + COFF_HEADER_LEN).ok_or_else(eof)?;
+        self.machine = LittleEndian::read_u16(header);
+        let nb_sections = LittleEndian::read_u16(&header[2..]);
+        self.timestamp = LittleEndian::read_u32(&header[4..]);
+        let symbol_table = LittleEndian::read_u32(&header[8..]) as u64;
+        let nb_symbols = LittleEndian::read_u32(&header[12..]) as u64;
+        let optional_header_len = LittleEndian::read_u16(&header[16..]) as usize;
+        self.characteristics = LittleEndian::read_u16(&header[18..]);
+        if nb_sections > MAX_SECTIONS {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        // the data directories follow the fields, which are wider in PE32+
+        let optional = coff + COFF_HEADER_LEN;
+        let header = data
+            .get(optional..optional + optional_header_len)
+            .ok_or_else(eof)?;
+        let directories = match header.get(..2).map(LittleEndian::read_u16) {
+            Some(PE32) => 96,
+            Some(PE32_PLUS) => 112,
+            _ => return err!(ErrorKind::InvalidData),
+        };
+        if optional_header_len < directories {
+            return err!(ErrorKind::InvalidData);
+        }
+        let file_alignment = LittleEndian::read_u32(&header[36..]);
+        let headers_len = LittleEndian::read_u32(&header[60..]) as u64;
+        let nb_directories = LittleEndian::read_u32(&header[directories - 4..]) as usize;
+        let sections = optional + optional_header_len;
+        if !file_alignment.is_power_of_two()
+            || directories + 8 * nb_directories > optional_header_len
+            || headers_len < (sections + SECTION_HEADER_LEN * nb_sections as usize) as u64
+        {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        // the raw data of the sections
+        let table = data
+            .get(sections..sections + SECTION_HEADER_LEN * nb_sections as usize)
+            .ok_or_else(eof)?;
+        self.size = table
+            .chunks_exact(SECTION_HEADER_LEN)
+            .map(|section| {
+                let raw_size = LittleEndian::read_u32(&section[16..]) as u64;
+                let raw_offset = LittleEndian::read_u32(&section[20..]) as u64;
+                if raw_size == 0 {
+                    0
+                } else {
+                    raw_offset + raw_size
+                }
+            })
+            .fold(headers_len, u64::max);
+
+        // the COFF symbol table some linkers leave, followed by its string table
+        if symbol_table != 0 {
+            let strings = symbol_table + SYMBOL_LEN * nb_symbols;
+            let strings_len = data
+                .get(strings as usize..strings as usize + 4)
+                .map_or(0, LittleEndian::read_u32);
+            self.size = self.size.max(strings + strings_len as u64);
+        }
+
+        // the certificate table of signed files is in the overlay, and its offset is a file one
+        if SECURITY_DIRECTORY < nb_directories {
+            let entry = &header[directories + 8 * SECURITY_DIRECTORY..];
+            let offset = LittleEndian::read_u32(entry) as u64;
+            let len = LittleEndian::read_u32(&entry[4..]) as u64;
+            if offset >= self.size && len != 0 {
+                self.size = offset + len;
+            }
+        }
+
+        buffer.set_position((sections + table.len()) as u64);
+        Ok(sections + table.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(raw_size: u32, raw_offset: u32) -> Vec<u8> {
+        let mut section = b".text\0\0\0".to_vec();
+        section.extend([0; 8]);
+        section.extend(raw_size.to_le_bytes());
+        section.extend(raw_offset.to_le_bytes());
+        section.extend([0; 16]);
+        section
+    }
+
+    // a PE32+ file with two sections and a certificate table after an overlay
+    fn file(characteristics: u16) -> Vec<u8> {
+        let mut pe = vec![0u8; 0x40];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3C] = 0x40;
+        pe.extend(SIGNATURE);
+
+        pe.extend(0x8664u16.to_le_bytes());
+        pe.extend(2u16.to_le_bytes());
+        pe.extend(1_700_000_000u32.to_le_bytes());
+        pe.extend([0; 8]);
+        pe.extend(240u16.to_le_bytes());
+        pe.extend(characteristics.to_le_bytes());
+
+        let mut optional = vec![0u8; 240];
+        optional[..2].copy_from_slice(&PE32_PLUS.to_le_bytes());
+        optional[36..40].copy_from_slice(&0x200u32.to_le_bytes());
+        optional[60..64].copy_from_slice(&0x200u32.to_le_bytes());
+        optional[108..112].copy_from_slice(&16u32.to_le_bytes());
+        optional[144..148].copy_from_slice(&0x700u32.to_le_bytes());
+        optional[148..152].copy_from_slice(&0x80u32.to_le_bytes());
+        pe.extend(optional);
+
+        pe.extend(section(0x200, 0x400));
+        pe.extend(section(0x200, 0x200));
+        pe.resize(0x780, 0xCC);
+        pe
+    }
+
+    #[test]
+    fn pe_sections() {
+        let data = file(EXECUTABLE_IMAGE | DLL);
+        let mut pe = PE::default();
+        pe.deserialize(&mut Cursor::new(data.as_slice())).unwrap();
+        assert!(pe.is_genuine());
+        assert_eq!(pe.size(), 0x780);
+        assert_eq!(pe.subtype(), Some(DLL_SUBTYPE));
+        assert_eq!(pe.metadata()[0].1, "x86_64");
+        assert_eq!(pe.metadata()[1].1, "2023-11-14 22:13:20");
+
+        // no certificate table: the overlay is unknown
+        let mut data = file(EXECUTABLE_IMAGE);
+        data[0x40 + 24 + 148..0x40 + 24 + 152].fill(0);
+        let mut pe = PE::default();
+        pe.deserialize(&mut Cursor::new(data.as_slice())).unwrap();
+        assert_eq!(pe.size(), 0x600);
+        assert_eq!(pe.subtype(), None);
+
+        // not a PE
+        data[0x40] = b'N';
+        assert!(
+            PE::default()
+                .deserialize(&mut Cursor::new(data.as_slice()))
+                .is_err()
+        );
+    }
+}
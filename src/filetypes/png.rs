@@ -5,8 +5,21 @@ use std::{
 };
 
 use byteorder::{BigEndian, ReadBytesExt};
+use miniz_oxide::inflate::{TINFLStatus, decompress_to_vec_zlib_with_limit};
 
-use crate::{carvers::fourcc_carver::FourCCCarver, deserializer::Deserializer, err};
+use crate::{
+    audit::Metadata,
+    carvers::{Subtype, fourcc_carver::FourCCCarver},
+    deserializer::Deserializer,
+    err,
+    validation::Validation,
+};
+
+// the width and the height are 31-bit
+const MAX_DIMENSION: u32 = (1 << 31) - 1;
+
+// the image data of larger images isn't decompressed, whatever their header says
+const MAX_IMAGE_DATA: usize = 256 * 1024 * 1024;
 
 #[derive(Debug, Default)]
 pub struct PNGHeader {
@@ -25,25 +38,29 @@ impl Deserializer for PNGHeader {
 #[derive(Debug, Default)]
 pub struct ChunkType([u8; 4]);
 
+// animated PNG
+const APNG: Subtype = Subtype {
+    ext: "apng",
+    category: "images/apng",
+};
+
+// chunk types defined by the PNG specification and its extensions
+const KNOWN_CHUNKS: [&[u8; 4]; 26] = [
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"tRNS", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB",
+    b"cICP", b"mDCV", b"cLLI", b"tEXt", b"zTXt", b"iTXt", b"bKGD", b"hIST", b"pHYs", b"sPLT",
+    b"eXIf", b"tIME", b"acTL", b"fcTL", b"fdAT", b"iDOT",
+];
+
 impl ChunkType {
-    // not all arrays of 2 bytes are valid Jpeg segments
+    // a chunk type is made of 4 ASCII letters, the case of each letter giving a property bit:
+    // ancillary, private, reserved and safe-to-copy. The reserved bit must be 0 (uppercase)
     fn is_valid(&self) -> bool {
-        if self.0 != *b"IHDR"
-            && self.0 != *b"PLTE"
-            && self.0 != *b"IDAT"
-            && self.0 != *b"IEND"
-            && self.0 != *b"tEXt"
-            && self.0 != *b"iTXt"
-            && self.0 != *b"tIME"
-            && self.0 != *b"gAMA"
-            && self.0 != *b"sRGB"
-            && self.0 != *b"iCCP"
-            && self.0 != *b"pHYs"
-        {
-            false
-        } else {
-            true
-        }
+        self.0.iter().all(|b| b.is_ascii_alphabetic()) && self.0[2].is_ascii_uppercase()
+    }
+
+    // true if this chunk type is defined by the PNG specification or its extensions
+    fn is_known(&self) -> bool {
+        KNOWN_CHUNKS.contains(&&self.0)
     }
 }
 
@@ -81,7 +98,7 @@ impl fmt::Debug for PNGChunk {
         write!(
             f,
             "chunk={}",
-            String::from_utf8(self.chunk_type.to_vec()).unwrap()
+            String::from_utf8_lossy(self.chunk_type.as_slice())
         )
     }
 }
@@ -91,8 +108,8 @@ impl Deserializer for PNGChunk {
         self.length = buffer.read_u32::<BigEndian>()?;
         let _ = self.chunk_type.deserialize(buffer)?;
 
-        // compare against usual chunk types
-        if !self.chunk_type.is_valid() {
+        // chunk type must follow the naming rules, and length is limited to 2^31-1
+        if !self.chunk_type.is_valid() || self.length > i32::MAX as u32 {
             return err!(ErrorKind::InvalidData);
         }
 
@@ -108,6 +125,175 @@ impl FourCCCarver for PNGChunk {
     fn is_end(&self) -> bool {
         self.chunk_type == *b"IEND"
     }
+
+    // the animation control chunk is only found in animated PNGs
+    fn subtype(&self) -> Option<Subtype> {
+        (self.chunk_type == *b"acTL").then_some(APNG)
+    }
+
+    // a chunk starts with its length, followed by its type: look for a known chunk type with
+    // a length which fits in what's left. Any 4 letters would be a valid chunk type, so only
+    // known ones are considered to limit false positives
+    fn resync(buffer: &[u8]) -> Option<usize> {
+        buffer.windows(8).enumerate().position(|(i, w)| {
+            let length = u32::from_be_bytes([w[0], w[1], w[2], w[3]]) as usize;
+            let chunk_type = ChunkType([w[4], w[5], w[6], w[7]]);
+            chunk_type.is_known() && length <= buffer.len() - i - 8
+        })
+    }
+}
+
+// the IHDR chunk data
+#[derive(Debug, Default)]
+struct ImageHeader {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    compression: u8,
+    filter: u8,
+    interlace: u8,
+}
+
+impl Deserializer for ImageHeader {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        self.width = buffer.read_u32::<BigEndian>()?;
+        self.height = buffer.read_u32::<BigEndian>()?;
+        self.bit_depth = buffer.read_u8()?;
+        self.color_type = buffer.read_u8()?;
+        self.compression = buffer.read_u8()?;
+        self.filter = buffer.read_u8()?;
+        self.interlace = buffer.read_u8()?;
+
+        Ok(13)
+    }
+}
+
+impl ImageHeader {
+    // number of bits per pixel, None if the bit depth is not allowed for the color type
+    fn bits_per_pixel(&self) -> Option<u64> {
+        let channels = match (self.color_type, self.bit_depth) {
+            (0, 1 | 2 | 4 | 8 | 16) => 1, // greyscale
+            (2, 8 | 16) => 3,             // truecolour
+            (3, 1 | 2 | 4 | 8) => 1,      // indexed-colour
+            (4, 8 | 16) => 2,             // greyscale with alpha
+            (6, 8 | 16) => 4,             // truecolour with alpha
+            _ => return None,
+        };
+
+        Some(channels * self.bit_depth as u64)
+    }
+
+    // length of the decompressed image data: each scanline is prefixed by its filter type byte
+    fn image_data_len(&self) -> Option<u64> {
+        if !(1..=MAX_DIMENSION).contains(&self.width)
+            || !(1..=MAX_DIMENSION).contains(&self.height)
+            || self.compression != 0
+            || self.filter != 0
+        {
+            return None;
+        }
+        let bpp = self.bits_per_pixel()?;
+        let (width, height) = (self.width as u64, self.height as u64);
+
+        // length of a reduced image, None if it overflows
+        let len = |w: u64, h: u64| {
+            if w == 0 || h == 0 {
+                Some(0)
+            } else {
+                h.checked_mul(w.checked_mul(bpp)?.div_ceil(8).checked_add(1)?)
+            }
+        };
+
+        match self.interlace {
+            0 => len(width, height),
+
+            // Adam7 interlacing: 7 reduced images, given by their starting column and row and
+            // their column and row increments
+            1 => {
+                const PASSES: [(u64, u64, u64, u64); 7] = [
+                    (0, 0, 8, 8),
+                    (4, 0, 8, 8),
+                    (0, 4, 4, 8),
+                    (2, 0, 4, 4),
+                    (0, 2, 2, 4),
+                    (1, 0, 2, 2),
+                    (0, 1, 1, 2),
+                ];
+                PASSES.iter().try_fold(0u64, |total, (x, y, dx, dy)| {
+                    total.checked_add(len(
+                        width.saturating_sub(*x).div_ceil(*dx),
+                        height.saturating_sub(*y).div_ceil(*dy),
+                    )?)
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// validate a carved PNG: check the CRC of all chunks, then decompress the image data and compare
+// its length to the one expected from the image header
+pub fn validate(payload: &[u8], _metadata: &mut Metadata) -> Validation {
+    // skip signature
+    let mut pos = 8;
+    let mut header: Option<ImageHeader> = None;
+    let mut image_data = Vec::new();
+
+    loop {
+        // chunk length, then type and data which are used for the CRC, then the CRC
+        let Some(length) = payload.get(pos..pos + 4) else {
+            return Validation::Truncated;
+        };
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let end = pos + 8 + length;
+
+        let (Some(data), Some(crc)) = (payload.get(pos + 4..end), payload.get(end..end + 4)) else {
+            return Validation::Truncated;
+        };
+        if crc32fast::hash(data) != u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) {
+            return Validation::Corrupt;
+        }
+
+        let (chunk_type, data) = data.split_at(4);
+        match chunk_type {
+            b"IHDR" => {
+                let mut ihdr = ImageHeader::default();
+                if ihdr.deserialize(&mut Cursor::new(data)).is_err() {
+                    return Validation::Corrupt;
+                }
+                header = Some(ihdr);
+            }
+            b"IDAT" => image_data.extend_from_slice(data),
+            b"IEND" => break,
+            _ => (),
+        }
+
+        // IHDR must be the first chunk
+        if header.is_none() {
+            return Validation::Corrupt;
+        }
+
+        pos = end + 4;
+    }
+
+    let Some(expected) = header.and_then(|ihdr| ihdr.image_data_len()) else {
+        return Validation::Corrupt;
+    };
+    let Ok(expected) = usize::try_from(expected) else {
+        return Validation::Corrupt;
+    };
+    if expected > MAX_IMAGE_DATA {
+        return Validation::Unsupported;
+    }
+
+    // never decompress more than expected
+    match decompress_to_vec_zlib_with_limit(&image_data, expected) {
+        Ok(data) if data.len() == expected => Validation::Valid,
+        Ok(_) => Validation::Corrupt,
+        Err(e) if e.status == TINFLStatus::FailedCannotMakeProgress => Validation::Truncated,
+        Err(_) => Validation::Corrupt,
+    }
 }
 
 #[cfg(test)]
@@ -119,8 +305,20 @@ mod tests {
     #[test]
     fn chunk_type() {
         let ct = ChunkType(*b"ABCD");
+        assert!(ct.is_valid());
+        assert!(!ct.is_known());
+
+        // reserved bit set
+        let ct = ChunkType(*b"ABcD");
         assert!(!ct.is_valid());
 
+        let ct = ChunkType(*b"AB1D");
+        assert!(!ct.is_valid());
+
+        let ct = ChunkType(*b"bKGD");
+        assert!(ct.is_valid());
+        assert!(ct.is_known());
+
         let ct = ChunkType(*b"IHDR");
         assert!(ct.is_valid());
 
@@ -143,5 +341,83 @@ mod tests {
 
         assert_eq!(chunk.length, 1);
         assert_eq!(&chunk.chunk_type, b"sRGB");
+        assert_eq!(chunk.subtype(), None);
+
+        let raw_data = hex!("00 00 00 08 61 63 54 4C 00 00 00 02 00 00 00 00 F3 8D 93 70");
+        let mut c = Cursor::new(raw_data.as_slice());
+        let mut chunk = PNGChunk::default();
+        chunk.deserialize(&mut c).unwrap();
+        assert_eq!(chunk.subtype(), Some(APNG));
+    }
+
+    // build a PNG of 3x2 truecolour pixels, or with the image header passed
+    fn png() -> Vec<u8> {
+        png_with(&hex!("00 00 00 03 00 00 00 02 08 02 00 00 00"))
+    }
+
+    fn png_with(ihdr: &[u8]) -> Vec<u8> {
+        let chunk = |chunk_type: &[u8], data: &[u8]| {
+            let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(chunk_type);
+            chunk.extend_from_slice(data);
+            chunk.extend_from_slice(&crc32fast::hash(&chunk[4..]).to_be_bytes());
+            chunk
+        };
+
+        let image_data = miniz_oxide::deflate::compress_to_vec_zlib(&[0u8; 2 * (1 + 9)], 6);
+
+        let mut png = hex!("89 50 4E 47 0D 0A 1A 0A").to_vec();
+        png.extend(chunk(b"IHDR", ihdr));
+        png.extend(chunk(b"IDAT", &image_data));
+        png.extend(chunk(b"IEND", &[]));
+        png
+    }
+
+    #[test]
+    fn validate_png() {
+        let png = png();
+        assert_eq!(validate(&png, &mut Vec::new()), Validation::Valid);
+
+        // image data modified
+        let mut corrupt = png.clone();
+        corrupt[45] ^= 0xFF;
+        assert_eq!(validate(&corrupt, &mut Vec::new()), Validation::Corrupt);
+
+        assert_eq!(
+            validate(&png[..png.len() - 6], &mut Vec::new()),
+            Validation::Truncated
+        );
+
+        // sizes which don't fit, or too much data to decompress
+        let ihdr = hex!("FF FF FF FF FF FF FF FF 10 06 00 00 00");
+        assert_eq!(
+            validate(&png_with(&ihdr), &mut Vec::new()),
+            Validation::Corrupt
+        );
+        let mut header = ImageHeader::default();
+        header
+            .deserialize(&mut Cursor::new(
+                hex!("7F FF FF FF 7F FF FF FF 10 06 00 00 00").as_slice(),
+            ))
+            .unwrap();
+        assert_eq!(header.image_data_len(), None);
+        header.interlace = 1;
+        assert_eq!(header.image_data_len(), None);
+        let ihdr = hex!("00 00 4E 20 00 00 4E 20 08 00 00 00 00");
+        assert_eq!(
+            validate(&png_with(&ihdr), &mut Vec::new()),
+            Validation::Unsupported
+        );
+    }
+
+    #[test]
+    fn resync() {
+        // garbage, then an IEND chunk
+        let raw_data = hex!("12 34 49 44 00 00 00 00 49 45 4E 44 AE 42 60 82");
+        assert_eq!(PNGChunk::resync(raw_data.as_slice()), Some(4));
+
+        // no chunk
+        let raw_data = hex!("12 34 49 44 00 00 00 00 49 45 4E");
+        assert_eq!(PNGChunk::resync(raw_data.as_slice()), None);
     }
 }
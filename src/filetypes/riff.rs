@@ -0,0 +1,274 @@
+use std::io::{Cursor, Read};
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+
+use crate::{
+    carvers::{Subtype, size_carver::SizeCarver},
+    deserializer::Deserializer,
+};
+
+// RIFF files are a header giving the file size and a form type, followed by chunks: a FourCC,
+// a size and data padded to an even length
+// see: https://www.mmsp.ece.mcgill.ca/Documents/AudioFormats/WAVE/Docs/riffmci.pdf
+// RF64 is the same for WAV files over 4 GB, the sizes which don't fit are in a ds64 chunk
+// see: https://tech.ebu.ch/docs/tech/tech3306v1_1.pdf
+
+// a size which doesn't fit, found in the ds64 chunk
+const RF64_SIZE: u32 = 0xFFFFFFFF;
+
+pub const AVI: Subtype = Subtype {
+    ext: "avi",
+    category: "videos/avi",
+};
+pub const WEBP: Subtype = Subtype {
+    ext: "webp",
+    category: "images/webp",
+};
+pub const ANI: Subtype = Subtype {
+    ext: "ani",
+    category: "images/ani",
+};
+pub const RMI: Subtype = Subtype {
+    ext: "rmi",
+    category: "audio/rmi",
+};
+pub const CDR: Subtype = Subtype {
+    ext: "cdr",
+    category: "images/cdr",
+};
+
+// FourCCs are printable characters
+fn is_fourcc(id: &[u8]) -> bool {
+    id.iter().all(|b| (0x20..0x7F).contains(b))
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default)]
+pub struct RIFF {
+    magic: [u8; 4],       // should be "RIFF", or "RF64"
+    size: u64,            // the size of the form type and the chunks, from ds64 for RF64
+    form_type: [u8; 4],   // like "WAVE" or "AVI "
+    chunks: Vec<[u8; 4]>, // the top-level chunks found
+    complete: bool,       // true if all the chunks were found in the buffer
+    consistent: bool,     // true if the chunks add up to the size
+    extensions: u64,      // size of the RIFF AVIX files following an AVI over 1 GB
+}
+
+impl RIFF {
+    // the form type must be a known one, with its mandatory chunks
+    fn is_known_form(&self) -> bool {
+        let has = |id: &[u8; 4]| !self.complete || self.chunks.contains(id);
+
+        match &self.form_type {
+            b"WAVE" => has(b"fmt ") && has(b"data"),
+            b"AVI " => has(b"LIST"),
+            b"WEBP" => matches!(self.chunks.first(), Some(b"VP8 " | b"VP8L" | b"VP8X")),
+            b"ACON" => has(b"anih"),
+            b"RMID" => has(b"data"),
+            [b'C' | b'c', b'D' | b'd', b'R' | b'r', _] => true,
+            _ => false,
+        }
+    }
+
+    // walk the chunks up to the size. The ones out of the buffer can't be checked
+    fn walk(&mut self, data: &[u8]) {
+        let rf64 = &self.magic == b"RF64";
+        let mut data_size = None;
+        let mut pos = 12u64;
+
+        // sizes could be anything, and are 64-bit for RF64
+        self.complete = true;
+        while let Some(end) = self.size.checked_add(8)
+            && pos < end
+        {
+            let Some(header) = data.get(pos as usize..(pos as usize).saturating_add(8)) else {
+                self.complete = false;
+                break;
+            };
+            let id: [u8; 4] = header[..4].try_into().unwrap();
+            let mut size = LittleEndian::read_u32(&header[4..]) as u64;
+            if !is_fourcc(&id) {
+                return;
+            }
+
+            // the sizes of the file and of the data chunk are in the first chunk for RF64
+            if rf64 && pos == 12 {
+                if &id != b"ds64" {
+                    return;
+                }
+                let Some(ds64) = data.get(pos as usize + 8..pos as usize + 24) else {
+                    return;
+                };
+                self.size = LittleEndian::read_u64(ds64);
+                data_size = Some(LittleEndian::read_u64(&ds64[8..]));
+            }
+            if &id == b"data"
+                && size == RF64_SIZE as u64
+                && let Some(data_size) = data_size
+            {
+                size = data_size;
+            }
+
+            self.chunks.push(id);
+            let Some(next) = size
+                .checked_add(8 + (size & 1))
+                .and_then(|len| pos.checked_add(len))
+            else {
+                return;
+            };
+            pos = next;
+        }
+
+        // some writers don't count the pad byte of the last chunk
+        let Some(end) = self.size.checked_add(8) else {
+            return;
+        };
+        self.consistent = !self.complete || (end..=end.saturating_add(1)).contains(&pos);
+    }
+
+    // an AVI over 1 GB goes on with RIFF AVIX extensions
+    fn extend(&mut self, data: &[u8]) {
+        if &self.form_type != b"AVI " {
+            return;
+        }
+
+        let Some(mut pos) = self.size.checked_add(8 + (self.size & 1)) else {
+            return;
+        };
+        while let Some(header) = data.get(pos as usize..(pos as usize).saturating_add(12))
+            && &header[..4] == b"RIFF"
+            && &header[8..] == b"AVIX"
+        {
+            let size = LittleEndian::read_u32(&header[4..]) as u64;
+            self.extensions += 8 + size + (size & 1);
+            pos += 8 + size + (size & 1);
+        }
+    }
+}
+
+impl SizeCarver for RIFF {
+    fn size(&self) -> usize {
+        self.size.saturating_add(8 + self.extensions) as usize
+    }
+
+    fn is_genuine(&self) -> bool {
+        (&self.magic == b"RIFF" || (&self.magic == b"RF64" && &self.form_type == b"WAVE"))
+            && self.consistent
+            && self.is_known_form()
+    }
+
+    fn ext(&self) -> String {
+        String::from("wav")
+    }
+
+    // WAV is the default
+    fn subtype(&self) -> Option<Subtype> {
+        match &self.form_type {
+            b"AVI " => Some(AVI),
+            b"WEBP" => Some(WEBP),
+            b"ACON" => Some(ANI),
+            b"RMID" => Some(RMI),
+            [b'C' | b'c', b'D' | b'd', b'R' | b'r', _] => Some(CDR),
+            _ => None,
+        }
+    }
+}
+
+impl Deserializer for RIFF {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let start = buffer.position() as usize;
+        buffer.read_exact(&mut self.magic)?;
+        self.size = buffer.read_u32::<LittleEndian>()? as u64;
+        buffer.read_exact(&mut self.form_type)?;
+
+        let data = &buffer.get_ref()[start..];
+        self.walk(data);
+        self.extend(data);
+
+        Ok(12)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_le_bytes());
+        chunk.extend(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn file(magic: &[u8; 4], size: u32, form_type: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut riff = magic.to_vec();
+        riff.extend(size.to_le_bytes());
+        riff.extend(form_type);
+        riff.extend(chunks.concat());
+        riff
+    }
+
+    fn carve(data: &[u8]) -> RIFF {
+        let mut riff = RIFF::default();
+        riff.deserialize(&mut Cursor::new(data)).unwrap();
+        riff
+    }
+
+    #[test]
+    fn riff_chunks() {
+        // WAV with an odd data chunk
+        let chunks = [chunk(b"fmt ", &[0; 16]), chunk(b"data", &[1; 7])];
+        let wav = file(b"RIFF", 4 + 24 + 16, b"WAVE", &chunks);
+        let riff = carve(&wav);
+        assert!(riff.is_genuine());
+        assert_eq!(riff.size(), wav.len());
+        assert_eq!(riff.subtype(), None);
+
+        // wrong size
+        let wav = file(b"RIFF", 4 + 24 + 8, b"WAVE", &chunks);
+        assert!(!carve(&wav).is_genuine());
+
+        // WebP
+        let webp = file(b"RIFF", 4 + 18, b"WEBP", &[chunk(b"VP8L", &[0; 10])]);
+        let riff = carve(&webp);
+        assert!(riff.is_genuine());
+        assert_eq!(riff.subtype(), Some(WEBP));
+
+        // RF64: the sizes are in ds64
+        let mut ds64 = 0u64.to_le_bytes().to_vec();
+        ds64.extend(8u64.to_le_bytes());
+        ds64.extend([0; 12]);
+        let mut rf64 = file(
+            b"RF64",
+            RF64_SIZE,
+            b"WAVE",
+            &[chunk(b"ds64", &ds64), chunk(b"fmt ", &[0; 16])],
+        );
+        rf64.extend(b"data");
+        rf64.extend(RF64_SIZE.to_le_bytes());
+        rf64.extend([2; 8]);
+        let size = rf64.len() as u64 - 8;
+        rf64[20..28].copy_from_slice(&size.to_le_bytes());
+        let riff = carve(&rf64);
+        assert!(riff.is_genuine());
+        assert_eq!(riff.size(), rf64.len());
+
+        // sizes which overflow
+        rf64[20..28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(!carve(&rf64).is_genuine());
+        rf64[20..28].copy_from_slice(&size.to_le_bytes());
+        rf64[28..36].copy_from_slice(&(u64::MAX - 4).to_le_bytes());
+        assert!(!carve(&rf64).is_genuine());
+
+        // AVI followed by an AVIX extension
+        let mut avi = file(b"RIFF", 4 + 10, b"AVI ", &[chunk(b"LIST", &[0; 2])]);
+        avi.extend(file(b"RIFF", 4 + 10, b"AVIX", &[chunk(b"LIST", &[0; 2])]));
+        let riff = carve(&avi);
+        assert!(riff.is_genuine());
+        assert_eq!(riff.size(), avi.len());
+        assert_eq!(riff.subtype(), Some(AVI));
+    }
+}
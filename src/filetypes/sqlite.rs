@@ -0,0 +1,333 @@
+use std::io::{Cursor, Error, ErrorKind, Read};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+
+use crate::{audit::Metadata, carvers::size_carver::SizeCarver, deserializer::Deserializer, err};
+
+// see: https://www.sqlite.org/fileformat.html
+const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+// the WAL magic, the last bit gives the byte order of the checksums
+const WAL_MAGIC: u32 = 0x377F0682;
+const WAL_VERSION: u32 = 3007000;
+
+// a page size is a power of two between 512 and 65536
+fn is_page_size(page_size: u32) -> bool {
+    page_size.is_power_of_two() && (512..=65536).contains(&page_size)
+}
+
+// the database header, in the first 100 bytes of the first page
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default)]
+pub struct SQLite {
+    magic: [u8; 16],         // should be "SQLite format 3\0"
+    page_size: u16,          // big endian, 1 means 65536
+    write_version: u8,       // 1 for legacy, 2 for WAL
+    read_version: u8,        // 1 for legacy, 2 for WAL
+    reserved: u8,            // unused bytes at the end of each page
+    max_payload: u8,         // must be 64
+    min_payload: u8,         // must be 32
+    leaf_payload: u8,        // must be 32
+    change_counter: u32,     // incremented each time the file is modified
+    nb_pages: u32,           // the size of the database in pages
+    freelist_trunk: u32,     // first freelist trunk page
+    freelist_pages: u32,     // total number of freelist pages
+    schema_cookie: u32,      // incremented each time the schema changes
+    schema_format: u32,      // 1, 2, 3 or 4
+    cache_size: u32,         // default page cache size
+    largest_root: u32,       // for auto-vacuum and incremental-vacuum modes
+    text_encoding: u32,      // 1 for UTF-8, 2 for UTF-16le, 3 for UTF-16be
+    user_version: u32,       // set by the user_version pragma
+    incremental_vacuum: u32, // non-zero for incremental-vacuum mode
+    application_id: u32,     // set by the application_id pragma
+    zeroes: [u8; 20],        // reserved for expansion, must be 0
+    version_valid_for: u32,  // the change counter when nb_pages was stored
+    sqlite_version: u32,     // the SQLite version which last modified the file
+}
+
+impl SQLite {
+    fn page_size(&self) -> u32 {
+        match self.page_size {
+            1 => 65536,
+            page_size => page_size as u32,
+        }
+    }
+}
+
+impl SizeCarver for SQLite {
+    fn size(&self) -> usize {
+        self.page_size() as usize * self.nb_pages as usize
+    }
+
+    // the database size is only valid when it was stored by the same change as the counter,
+    // which is not the case with legacy versions of SQLite
+    fn is_genuine(&self) -> bool {
+        &self.magic == MAGIC
+            && is_page_size(self.page_size())
+            && (1..=2).contains(&self.write_version)
+            && (1..=2).contains(&self.read_version)
+            && self.max_payload == 64
+            && self.min_payload == 32
+            && self.leaf_payload == 32
+            && (1..=4).contains(&self.schema_format)
+            && (1..=3).contains(&self.text_encoding)
+            && self.zeroes == [0; 20]
+            && self.nb_pages != 0
+            && self.change_counter == self.version_valid_for
+    }
+
+    fn ext(&self) -> String {
+        String::from("sqlite")
+    }
+
+    fn metadata(&self) -> Metadata {
+        vec![
+            ("page_size", self.page_size().to_string()),
+            ("pages", self.nb_pages.to_string()),
+            (
+                "sqlite_version",
+                format!(
+                    "{}.{}.{}",
+                    self.sqlite_version / 1_000_000,
+                    self.sqlite_version / 1000 % 1000,
+                    self.sqlite_version % 1000
+                ),
+            ),
+        ]
+    }
+}
+
+impl Deserializer for SQLite {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        buffer.read_exact(&mut self.magic)?;
+        self.page_size = buffer.read_u16::<BigEndian>()?;
+        self.write_version = buffer.read_u8()?;
+        self.read_version = buffer.read_u8()?;
+        self.reserved = buffer.read_u8()?;
+        self.max_payload = buffer.read_u8()?;
+        self.min_payload = buffer.read_u8()?;
+        self.leaf_payload = buffer.read_u8()?;
+        self.change_counter = buffer.read_u32::<BigEndian>()?;
+        self.nb_pages = buffer.read_u32::<BigEndian>()?;
+        self.freelist_trunk = buffer.read_u32::<BigEndian>()?;
+        self.freelist_pages = buffer.read_u32::<BigEndian>()?;
+        self.schema_cookie = buffer.read_u32::<BigEndian>()?;
+        self.schema_format = buffer.read_u32::<BigEndian>()?;
+        self.cache_size = buffer.read_u32::<BigEndian>()?;
+        self.largest_root = buffer.read_u32::<BigEndian>()?;
+        self.text_encoding = buffer.read_u32::<BigEndian>()?;
+        self.user_version = buffer.read_u32::<BigEndian>()?;
+        self.incremental_vacuum = buffer.read_u32::<BigEndian>()?;
+        self.application_id = buffer.read_u32::<BigEndian>()?;
+        buffer.read_exact(&mut self.zeroes)?;
+        self.version_valid_for = buffer.read_u32::<BigEndian>()?;
+        self.sqlite_version = buffer.read_u32::<BigEndian>()?;
+
+        Ok(100)
+    }
+}
+
+// the checksum of the WAL, computed on pairs of 32-bit words and carried over from the header
+// to each frame in turn
+fn wal_checksum(data: &[u8], big_endian: bool, (mut s0, mut s1): (u32, u32)) -> (u32, u32) {
+    for words in data.chunks_exact(8) {
+        let (x0, x1) = if big_endian {
+            (BigEndian::read_u32(words), BigEndian::read_u32(&words[4..]))
+        } else {
+            (
+                LittleEndian::read_u32(words),
+                LittleEndian::read_u32(&words[4..]),
+            )
+        };
+        s0 = s0.wrapping_add(x0).wrapping_add(s1);
+        s1 = s1.wrapping_add(x1).wrapping_add(s0);
+    }
+    (s0, s1)
+}
+
+// the write-ahead log of a database: a 32-byte header followed by frames, each one being a
+// 24-byte header and a page. The WAL has no size, so the frames are followed as long as their
+// salts match the header and their checksums are right: the next ones are left over from
+// previous checkpoints
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default)]
+pub struct WAL {
+    magic: u32,           // 0x377F0682 or 0x377F0683
+    version: u32,         // should be 3007000
+    page_size: u32,       // the database page size
+    checkpoint: u32,      // checkpoint sequence number
+    salt: (u32, u32),     // random values, changed at each checkpoint
+    checksum: (u32, u32), // checksum of the first 24 bytes of the header
+    nb_frames: usize,     // number of valid frames following the header
+    nb_commits: usize,    // number of frames ending a transaction
+}
+
+impl SizeCarver for WAL {
+    fn size(&self) -> usize {
+        32 + self.nb_frames * (24 + self.page_size as usize)
+    }
+
+    fn is_genuine(&self) -> bool {
+        self.magic & !1 == WAL_MAGIC && self.version == WAL_VERSION && is_page_size(self.page_size)
+    }
+
+    fn ext(&self) -> String {
+        String::from("wal")
+    }
+
+    fn metadata(&self) -> Metadata {
+        vec![
+            ("page_size", self.page_size.to_string()),
+            ("frames", self.nb_frames.to_string()),
+            ("commits", self.nb_commits.to_string()),
+        ]
+    }
+}
+
+impl Deserializer for WAL {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let start = buffer.position() as usize;
+        self.magic = buffer.read_u32::<BigEndian>()?;
+        self.version = buffer.read_u32::<BigEndian>()?;
+        self.page_size = buffer.read_u32::<BigEndian>()?;
+        self.checkpoint = buffer.read_u32::<BigEndian>()?;
+        self.salt = (
+            buffer.read_u32::<BigEndian>()?,
+            buffer.read_u32::<BigEndian>()?,
+        );
+        self.checksum = (
+            buffer.read_u32::<BigEndian>()?,
+            buffer.read_u32::<BigEndian>()?,
+        );
+
+        let data = &buffer.get_ref()[start..];
+        let big_endian = self.magic & 1 == 1;
+        if !self.is_genuine() || wal_checksum(&data[..24], big_endian, (0, 0)) != self.checksum {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        // the frames
+        let frame_size = 24 + self.page_size as usize;
+        let mut checksum = self.checksum;
+        for frame in data[32..].chunks_exact(frame_size) {
+            let salt = (
+                BigEndian::read_u32(&frame[8..]),
+                BigEndian::read_u32(&frame[12..]),
+            );
+            if salt != self.salt {
+                break;
+            }
+
+            // the checksum covers the first 8 bytes of the frame header and the page
+            let next = wal_checksum(
+                &frame[24..],
+                big_endian,
+                wal_checksum(&frame[..8], big_endian, checksum),
+            );
+            if next
+                != (
+                    BigEndian::read_u32(&frame[16..]),
+                    BigEndian::read_u32(&frame[20..]),
+                )
+            {
+                break;
+            }
+            checksum = next;
+
+            self.nb_frames += 1;
+            if BigEndian::read_u32(&frame[4..]) != 0 {
+                self.nb_commits += 1;
+            }
+        }
+
+        Ok(self.size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(page_size: u16, nb_pages: u32, valid_for: u32) -> Vec<u8> {
+        let mut db = MAGIC.to_vec();
+        db.extend(page_size.to_be_bytes());
+        db.extend([1, 1, 0, 64, 32, 32]);
+        db.extend(7u32.to_be_bytes());
+        db.extend(nb_pages.to_be_bytes());
+        db.extend([0; 12]);
+        db.extend(4u32.to_be_bytes());
+        db.extend([0; 8]);
+        db.extend(1u32.to_be_bytes());
+        db.extend([0; 12]);
+        db.extend([0; 20]);
+        db.extend(valid_for.to_be_bytes());
+        db.extend(3045001u32.to_be_bytes());
+        db
+    }
+
+    #[test]
+    fn sqlite_header() {
+        let db = database(4096, 3, 7);
+        let mut header = SQLite::default();
+        header.deserialize(&mut Cursor::new(db.as_slice())).unwrap();
+        assert!(header.is_genuine());
+        assert_eq!(header.size(), 3 * 4096);
+        assert_eq!(header.metadata()[2].1, "3.45.1");
+
+        // 65536 is stored as 1
+        let db = database(1, 2, 7);
+        let mut header = SQLite::default();
+        header.deserialize(&mut Cursor::new(db.as_slice())).unwrap();
+        assert_eq!(header.size(), 2 * 65536);
+
+        // not a power of two, or a stale size
+        for db in [database(1000, 3, 7), database(4096, 3, 6)] {
+            let mut header = SQLite::default();
+            header.deserialize(&mut Cursor::new(db.as_slice())).unwrap();
+            assert!(!header.is_genuine());
+        }
+    }
+
+    #[test]
+    fn wal_frames() {
+        // header, then 2 frames of 512 bytes
+        let mut wal = Vec::new();
+        for value in [WAL_MAGIC, WAL_VERSION, 512, 0, 0x1234, 0x5678] {
+            wal.extend(value.to_be_bytes());
+        }
+        let mut checksum = wal_checksum(&wal, false, (0, 0));
+        wal.extend(checksum.0.to_be_bytes());
+        wal.extend(checksum.1.to_be_bytes());
+
+        for (page, commit) in [(1u32, 0u32), (2, 2)] {
+            let mut frame = Vec::new();
+            for value in [page, commit, 0x1234, 0x5678] {
+                frame.extend(value.to_be_bytes());
+            }
+            let data = [page as u8; 512];
+            checksum = wal_checksum(&data, false, wal_checksum(&frame[..8], false, checksum));
+            frame.extend(checksum.0.to_be_bytes());
+            frame.extend(checksum.1.to_be_bytes());
+            frame.extend(data);
+            wal.extend(frame);
+        }
+
+        // a frame from a previous checkpoint
+        wal.extend([0xAA; 24 + 512]);
+
+        let mut header = WAL::default();
+        header
+            .deserialize(&mut Cursor::new(wal.as_slice()))
+            .unwrap();
+        assert_eq!((header.nb_frames, header.nb_commits), (2, 1));
+        assert_eq!(header.size(), 32 + 2 * (24 + 512));
+
+        // damaged page
+        wal[32 + 24 + 512 + 30] ^= 1;
+        let mut header = WAL::default();
+        header
+            .deserialize(&mut Cursor::new(wal.as_slice()))
+            .unwrap();
+        assert_eq!(header.nb_frames, 1);
+    }
+}
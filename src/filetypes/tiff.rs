@@ -0,0 +1,664 @@
+// TIFF structures: a header giving the byte order, followed by chains of IFDs (Image File
+// Directories) made of tagged entries. They're also found inside JPEG segments (EXIF, MPF)
+// see: https://www.itu.int/itudoc/itu-t/com16/tiff-fx/docs/tiff6.pdf
+use std::{
+    collections::HashSet,
+    io::{Cursor, Error, ErrorKind},
+    ops::Range,
+};
+
+use crate::{
+    audit::Metadata,
+    carvers::{Subtype, size_carver::SizeCarver},
+    deserializer::Deserializer,
+    err,
+};
+
+// maximum number of entries of an IFD
+const MAX_ENTRIES: usize = 4096;
+
+// a TIFF structure, at the start of data
+#[derive(Debug)]
+pub struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+    big: bool, // BigTIFF: 64-bit offsets and counts
+}
+
+// an entry of an IFD
+#[derive(Debug, Clone, Copy)]
+pub struct Entry {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u64,
+    value: usize, // offset of the value field in the TIFF data
+}
+
+// an IFD, and the offset of the next one in the chain (0 for the last one)
+#[derive(Debug, Default)]
+pub struct Ifd {
+    pub entries: Vec<Entry>,
+    pub next: u64,
+    pub end: u64, // offset right after the IFD
+}
+
+impl Ifd {
+    pub fn get(&self, tag: u16) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+}
+
+impl<'a> Tiff<'a> {
+    // check the byte order and the magic number
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let (little_endian, big) = match data.get(..4)? {
+            b"II\x2A\x00" => (true, false),
+            b"MM\x00\x2A" => (false, false),
+            b"II\x2B\x00" => (true, true),
+            b"MM\x00\x2B" => (false, true),
+            _ => return None,
+        };
+
+        let tiff = Self {
+            data,
+            little_endian,
+            big,
+        };
+
+        // BigTIFF: size of the offsets, always 8, followed by 0
+        if big && (tiff.u16(4)? != 8 || tiff.u16(6)? != 0) {
+            return None;
+        }
+
+        Some(tiff)
+    }
+
+    // length of the header
+    pub fn header_len(&self) -> u64 {
+        if self.big { 16 } else { 8 }
+    }
+
+    pub fn u16(&self, offset: usize) -> Option<u16> {
+        self.uint(offset, 2).map(|v| v as u16)
+    }
+
+    pub fn u32(&self, offset: usize) -> Option<u32> {
+        self.uint(offset, 4).map(|v| v as u32)
+    }
+
+    pub fn u64(&self, offset: usize) -> Option<u64> {
+        self.uint(offset, 8)
+    }
+
+    // read an unsigned integer of len bytes using the byte order
+    fn uint(&self, offset: usize, len: usize) -> Option<u64> {
+        self.data
+            .get(offset..offset.checked_add(len)?)
+            .map(|bytes| self.to_uint(bytes))
+    }
+
+    // convert bytes using the byte order
+    fn to_uint(&self, bytes: &[u8]) -> u64 {
+        let fold = |acc: u64, b: &u8| (acc << 8) | *b as u64;
+        if self.little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        }
+    }
+
+    // an offset, which is 64-bit for BigTIFF
+    fn offset(&self, offset: usize) -> Option<u64> {
+        if self.big {
+            self.u64(offset)
+        } else {
+            self.u32(offset).map(|v| v as u64)
+        }
+    }
+
+    // offset of the first IFD
+    pub fn first_ifd(&self) -> Option<u64> {
+        if self.big {
+            self.u64(8)
+        } else {
+            self.offset(4)
+        }
+    }
+
+    // read the IFD at offset
+    pub fn ifd(&self, offset: u64) -> Option<Ifd> {
+        // a TIFF entry is 12 bytes long, 20 bytes for BigTIFF
+        let (count_len, entry_len) = if self.big { (8, 20) } else { (2, 12) };
+
+        let offset = usize::try_from(offset).ok()?;
+        let nb = self.uint(offset, count_len)? as usize;
+
+        // directories are small: don't trust a huge number of entries
+        if nb > MAX_ENTRIES {
+            return None;
+        }
+
+        let mut entries = Vec::with_capacity(nb);
+        for i in 0..nb {
+            let pos = offset + count_len + entry_len * i;
+            entries.push(Entry {
+                tag: self.u16(pos)?,
+                field_type: self.u16(pos + 2)?,
+                count: self.offset(pos + 4)?,
+                value: pos + entry_len - count_len.max(4),
+            });
+        }
+
+        let next = offset + count_len + entry_len * nb;
+        Some(Ifd {
+            entries,
+            next: self.offset(next)?,
+            end: (next + count_len.max(4)) as u64,
+        })
+    }
+
+    // size of the value of an entry
+    fn value_len(entry: &Entry) -> Option<u64> {
+        let size: u64 = match entry.field_type {
+            1 | 2 | 6 | 7 => 1,         // BYTE, ASCII, SBYTE, UNDEFINED
+            3 | 8 => 2,                 // SHORT, SSHORT
+            4 | 9 | 11 | 13 => 4,       // LONG, SLONG, FLOAT, IFD
+            5 | 10 | 12 | 16..=18 => 8, // RATIONAL, SRATIONAL, DOUBLE, LONG8, SLONG8, IFD8
+            _ => return None,
+        };
+        size.checked_mul(entry.count)
+    }
+
+    // where the value of an entry is: stored in the entry itself if it fits, otherwise the entry
+    // holds its offset
+    fn value_range(&self, entry: &Entry) -> Option<Range<u64>> {
+        let len = Self::value_len(entry)?;
+        let inline = if self.big { 8 } else { 4 };
+
+        let start = if len <= inline {
+            entry.value as u64
+        } else {
+            self.offset(entry.value)?
+        };
+        Some(start..start.checked_add(len)?)
+    }
+
+    // bytes of the value of an entry
+    pub fn bytes(&self, entry: &Entry) -> Option<&'a [u8]> {
+        let range = self.value_range(entry)?;
+        self.data
+            .get(usize::try_from(range.start).ok()?..usize::try_from(range.end).ok()?)
+    }
+
+    // the value of an ASCII entry, without the trailing NUL bytes and spaces
+    pub fn string(&self, entry: &Entry) -> Option<String> {
+        if entry.field_type != 2 {
+            return None;
+        }
+        let bytes = self.bytes(entry)?;
+        let s = String::from_utf8_lossy(bytes);
+        let s = s.trim_end_matches(['\0', ' ']).trim_start();
+        (!s.is_empty()).then(|| s.to_string())
+    }
+
+    // the values of a RATIONAL entry
+    pub fn rationals(&self, entry: &Entry) -> Option<Vec<f64>> {
+        if entry.field_type != 5 {
+            return None;
+        }
+        let bytes = self.bytes(entry)?;
+        let values = bytes
+            .chunks_exact(8)
+            .map(|r| self.to_uint(&r[..4]) as f64 / self.to_uint(&r[4..]) as f64)
+            .collect();
+        Some(values)
+    }
+
+    // the values of an entry holding integers or offsets
+    pub fn values(&self, entry: &Entry) -> Option<Vec<u64>> {
+        let size = match entry.field_type {
+            3 => 2,
+            4 | 13 => 4,
+            16 | 18 if self.big => 8,
+            _ => return None,
+        };
+        let bytes = self.bytes(entry)?;
+        Some(bytes.chunks_exact(size).map(|b| self.to_uint(b)).collect())
+    }
+
+    // the value of an entry holding a single integer or offset
+    pub fn value(&self, entry: &Entry) -> Option<u64> {
+        if entry.count != 1 {
+            return None;
+        }
+        self.values(entry)?.first().copied()
+    }
+}
+
+// TIFF-based camera RAW files
+const CR2: Subtype = Subtype {
+    ext: "cr2",
+    category: "images/cr2",
+};
+const NEF: Subtype = Subtype {
+    ext: "nef",
+    category: "images/nef",
+};
+const ARW: Subtype = Subtype {
+    ext: "arw",
+    category: "images/arw",
+};
+const DNG: Subtype = Subtype {
+    ext: "dng",
+    category: "images/dng",
+};
+
+// tags used to find the extent of the file
+const IMAGE_WIDTH: u16 = 0x0100;
+const MAKE: u16 = 0x010F;
+const STRIP_OFFSETS: u16 = 0x0111;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TILE_OFFSETS: u16 = 0x0144;
+const TILE_BYTE_COUNTS: u16 = 0x0145;
+const SUB_IFDS: u16 = 0x014A;
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const INTEROPERABILITY_IFD: u16 = 0xA005;
+const DNG_VERSION: u16 = 0xC612;
+
+// maximum number of IFDs walked
+const MAX_IFDS: usize = 1024;
+
+// a TIFF file, or a camera RAW file based on TIFF. There's no size in the header: the file ends
+// with the furthest IFD or data referenced by an IFD
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default)]
+pub struct TIFF {
+    size: u64,                // end of the furthest IFD or data
+    nb_ifds: usize,           // number of IFDs walked
+    subtype: Option<Subtype>, // RAW file
+    metadata: Metadata,       // camera details
+}
+
+impl TIFF {
+    // walk all IFDs, starting from the first one, following the chains and the sub-IFDs
+    fn walk(&mut self, tiff: &Tiff) -> Option<()> {
+        let mut queue = vec![tiff.first_ifd()?];
+        let mut visited = HashSet::new();
+        self.size = tiff.header_len();
+
+        while let Some(offset) = queue.pop() {
+            if offset == 0 || visited.len() >= MAX_IFDS || !visited.insert(offset) {
+                continue;
+            }
+
+            // the first IFD must be there, the others could be damaged
+            let Some(ifd) = tiff.ifd(offset) else {
+                if self.nb_ifds == 0 {
+                    return None;
+                }
+                continue;
+            };
+            self.nb_ifds += 1;
+            self.size = self.size.max(ifd.end);
+
+            // values not stored in the entries themselves
+            for entry in &ifd.entries {
+                if let Some(range) = tiff.value_range(entry) {
+                    self.size = self.size.max(range.end);
+                }
+            }
+
+            // image data: strips, tiles or JPEG
+            for (offsets, lengths) in [
+                (STRIP_OFFSETS, STRIP_BYTE_COUNTS),
+                (TILE_OFFSETS, TILE_BYTE_COUNTS),
+                (JPEG_INTERCHANGE_FORMAT, JPEG_INTERCHANGE_FORMAT_LENGTH),
+            ] {
+                let values = |tag| ifd.get(tag).and_then(|e| tiff.values(e));
+                if let (Some(offsets), Some(lengths)) = (values(offsets), values(lengths)) {
+                    for (offset, length) in offsets.iter().zip(lengths) {
+                        self.size = self.size.max(offset.saturating_add(length));
+                    }
+                }
+            }
+
+            // sub-IFDs
+            for tag in [SUB_IFDS, EXIF_IFD, GPS_IFD, INTEROPERABILITY_IFD] {
+                if let Some(offsets) = ifd.get(tag).and_then(|e| tiff.values(e)) {
+                    queue.extend(offsets);
+                }
+            }
+            queue.push(ifd.next);
+        }
+
+        Some(())
+    }
+
+    // the RAW files are told by the camera make, or the DNG version
+    fn classify(&mut self, tiff: &Tiff, ifd0: &Ifd) {
+        let make = ifd0.get(MAKE).and_then(|e| tiff.string(e));
+
+        self.subtype = if ifd0.get(DNG_VERSION).is_some() {
+            Some(DNG)
+        } else {
+            match make.as_deref() {
+                Some(make) if make.starts_with("Canon") && tiff.data.get(8..10) == Some(b"CR") => {
+                    Some(CR2)
+                }
+                Some(make) if make.starts_with("NIKON") => Some(NEF),
+                Some(make) if make.starts_with("SONY") => Some(ARW),
+                _ => None,
+            }
+        };
+    }
+}
+
+impl SizeCarver for TIFF {
+    fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    fn is_genuine(&self) -> bool {
+        self.nb_ifds > 0
+    }
+
+    fn ext(&self) -> String {
+        String::from("tif")
+    }
+
+    fn subtype(&self) -> Option<Subtype> {
+        self.subtype
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+}
+
+impl Deserializer for TIFF {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let Some(tiff) = Tiff::new(buffer.get_ref()) else {
+            return err!(ErrorKind::InvalidData);
+        };
+
+        // the first IFD describes the main image, and has valid entries
+        let Some(ifd0) = tiff.first_ifd().and_then(|offset| tiff.ifd(offset)) else {
+            return err!(ErrorKind::InvalidData);
+        };
+        if ifd0.get(IMAGE_WIDTH).is_none()
+            || ifd0
+                .entries
+                .iter()
+                .any(|e| !(1..=18).contains(&e.field_type))
+        {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        if self.walk(&tiff).is_none() {
+            return err!(ErrorKind::InvalidData);
+        }
+        self.classify(&tiff, &ifd0);
+        self.metadata = exif_metadata(&tiff);
+
+        buffer.set_position(tiff.header_len());
+        Ok(tiff.header_len() as usize)
+    }
+}
+
+// camera details found in the EXIF IFDs, which investigators are interested in
+pub fn exif_metadata(tiff: &Tiff) -> Metadata {
+    const MODEL: u16 = 0x0110;
+    const CAMERA_SERIAL_NUMBER: u16 = 0xC62F;
+    const DATE_TIME_ORIGINAL: u16 = 0x9003;
+    const BODY_SERIAL_NUMBER: u16 = 0xA431;
+    const LENS_SERIAL_NUMBER: u16 = 0xA435;
+
+    let mut metadata = Metadata::new();
+    let Some(ifd0) = tiff.first_ifd().and_then(|offset| tiff.ifd(offset)) else {
+        return metadata;
+    };
+
+    // string tags of an IFD
+    let mut strings = |ifd: &Ifd, tags: &[(u16, &'static str)]| {
+        for (tag, key) in tags {
+            if let Some(value) = ifd.get(*tag).and_then(|e| tiff.string(e)) {
+                metadata.push((key, value));
+            }
+        }
+    };
+
+    strings(
+        &ifd0,
+        &[
+            (MAKE, "make"),
+            (MODEL, "model"),
+            (CAMERA_SERIAL_NUMBER, "camera_serial"),
+        ],
+    );
+
+    let sub_ifd = |tag| {
+        ifd0.get(tag)
+            .and_then(|e| tiff.value(e))
+            .and_then(|offset| tiff.ifd(offset))
+    };
+
+    if let Some(exif) = sub_ifd(EXIF_IFD) {
+        strings(
+            &exif,
+            &[
+                (DATE_TIME_ORIGINAL, "datetime_original"),
+                (BODY_SERIAL_NUMBER, "body_serial"),
+                (LENS_SERIAL_NUMBER, "lens_serial"),
+            ],
+        );
+    }
+
+    if let Some(gps) = sub_ifd(GPS_IFD)
+        && let Some(gps) = gps_coordinates(tiff, &gps)
+    {
+        metadata.push(("gps", gps));
+    }
+
+    metadata
+}
+
+// latitude and longitude in decimal degrees, from the GPS IFD
+fn gps_coordinates(tiff: &Tiff, gps: &Ifd) -> Option<String> {
+    const GPS_LATITUDE_REF: u16 = 1;
+    const GPS_LATITUDE: u16 = 2;
+    const GPS_LONGITUDE_REF: u16 = 3;
+    const GPS_LONGITUDE: u16 = 4;
+
+    // degrees, minutes and seconds, negative for the south and west references
+    let coordinate = |value_tag, ref_tag, negative| {
+        let dms = tiff.rationals(gps.get(value_tag)?)?;
+        let [d, m, s] = dms[..] else {
+            return None;
+        };
+        let degrees = d + m / 60.0 + s / 3600.0;
+        let reference = gps.get(ref_tag).and_then(|e| tiff.string(e));
+        match reference.as_deref() {
+            Some(r) if r == negative => Some(-degrees),
+            _ => Some(degrees),
+        }
+    };
+
+    let latitude = coordinate(GPS_LATITUDE, GPS_LATITUDE_REF, "S")?;
+    let longitude = coordinate(GPS_LONGITUDE, GPS_LONGITUDE_REF, "W")?;
+    (latitude.is_finite() && longitude.is_finite())
+        .then(|| format!("{:.6},{:.6}", latitude, longitude))
+}
+
+// the EXIF thumbnail, described by IFD1: its range in the TIFF data
+pub fn exif_thumbnail(tiff: &Tiff) -> Option<Range<u64>> {
+    let ifd0 = tiff.ifd(tiff.first_ifd()?)?;
+    let ifd1 = tiff.ifd(ifd0.next).filter(|_| ifd0.next != 0)?;
+
+    let offset = tiff.value(ifd1.get(JPEG_INTERCHANGE_FORMAT)?)?;
+    let length = tiff.value(ifd1.get(JPEG_INTERCHANGE_FORMAT_LENGTH)?)?;
+    Some(offset..offset.checked_add(length)?)
+}
+
+// the images listed in the MP Index IFD of the MPF format, except the first one which is the image
+// holding the MPF segment: their ranges in the TIFF data. Entries going past the data are ignored
+// see: https://web.archive.org/web/20190713230858/http://www.cipa.jp/std/documents/e/DC-007_E.pdf
+pub fn mpf_images(tiff: &Tiff) -> Option<Vec<Range<u64>>> {
+    const MP_ENTRY: u16 = 0xB002;
+
+    let ifd = tiff.ifd(tiff.first_ifd()?)?;
+    let entries = tiff.bytes(ifd.get(MP_ENTRY)?)?;
+
+    // each entry is 16 bytes: attributes, size, offset and 2 dependent images
+    let mut images = Vec::new();
+    for entry in entries.chunks_exact(16).skip(1) {
+        let size = tiff.to_uint(&entry[4..8]);
+        let offset = tiff.to_uint(&entry[8..12]);
+
+        if let Some(end) = offset.checked_add(size)
+            && offset > 0
+            && size > 0
+            && end <= tiff.data.len() as u64
+        {
+            images.push(offset..end);
+        }
+    }
+
+    Some(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn ifd() {
+        // little endian, IFD0 with one entry pointing to IFD1 holding a thumbnail
+        let data = hex!(
+            "49 49 2A 00 08 00 00 00
+             01 00 0F 01 02 00 04 00 00 00 41 42 43 00 1A 00 00 00
+             02 00 01 02 04 00 01 00 00 00 00 01 00 00 02 02 03 00 01 00 00 00 34 12 00 00 00 00 00 00"
+        );
+        let tiff = Tiff::new(&data).unwrap();
+        let ifd0 = tiff.ifd(tiff.first_ifd().unwrap()).unwrap();
+        assert_eq!(ifd0.entries.len(), 1);
+        assert_eq!(tiff.bytes(ifd0.get(0x010F).unwrap()).unwrap(), b"ABC\0");
+        assert_eq!(ifd0.next, 0x1A);
+
+        assert_eq!(exif_thumbnail(&tiff), Some(0x100..0x1334));
+
+        // LONG8 is only for BigTIFF
+        let mut long8 = data;
+        long8[0x1A + 2 + 12 + 2] = 16;
+        assert_eq!(exif_thumbnail(&Tiff::new(&long8).unwrap()), None);
+
+        // a BigTIFF thumbnail whose end overflows
+        let mut data = b"II\x2B\x00\x08\x00\x00\x00".to_vec();
+        data.extend(16u64.to_le_bytes());
+        data.extend(0u64.to_le_bytes());
+        data.extend(32u64.to_le_bytes());
+        data.extend(2u64.to_le_bytes());
+        for tag in [JPEG_INTERCHANGE_FORMAT, JPEG_INTERCHANGE_FORMAT_LENGTH] {
+            data.extend(tag.to_le_bytes());
+            data.extend(16u16.to_le_bytes());
+            data.extend(1u64.to_le_bytes());
+            data.extend(u64::MAX.to_le_bytes());
+        }
+        data.extend(0u64.to_le_bytes());
+        assert_eq!(exif_thumbnail(&Tiff::new(&data).unwrap()), None);
+        data[52..60].copy_from_slice(&0x100u64.to_le_bytes());
+        data[72..80].copy_from_slice(&0x20u64.to_le_bytes());
+        assert_eq!(
+            exif_thumbnail(&Tiff::new(&data).unwrap()),
+            Some(0x100..0x120)
+        );
+
+        // big endian MPF: 2 images, the second one at offset 0x1000
+        let mut data = hex!(
+            "4D 4D 00 2A 00 00 00 08
+             00 01 B0 02 00 07 00 00 00 20 00 00 00 1A 00 00 00 00
+             00 00 00 00 00 00 80 00 00 00 00 00 00 00 00 00
+             00 00 00 00 00 00 02 00 00 00 10 00 00 00 00 00"
+        )
+        .to_vec();
+        data.resize(0x1200, 0);
+        let tiff = Tiff::new(&data).unwrap();
+        let images = mpf_images(&tiff).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0], 0x1000..0x1200);
+
+        // corrupt entries: past the data, or overflowing
+        assert!(
+            mpf_images(&Tiff::new(&data[..0x11FF]).unwrap())
+                .unwrap()
+                .is_empty()
+        );
+        data[0x32..0x36].copy_from_slice(&[0xFF; 4]);
+        assert!(mpf_images(&Tiff::new(&data).unwrap()).unwrap().is_empty());
+
+        assert!(Tiff::new(b"II\x2B\x00").is_none());
+    }
+
+    #[test]
+    fn exif() {
+        // make, model, Exif IFD with the date and serial number, GPS IFD
+        let data = hex!(
+            "49 49 2A 00 08 00 00 00 04 00 0F 01 02 00 06 00 00 00 3E 00 00 00 10 01
+             02 00 0D 00 00 00 44 00 00 00 69 87 04 00 01 00 00 00 52 00 00 00 25 88
+             04 00 01 00 00 00 8C 00 00 00 00 00 00 00 43 61 6E 6F 6E 00 43 61 6E 6F
+             6E 20 45 4F 53 20 35 44 00 00 02 00 03 90 02 00 14 00 00 00 70 00 00 00
+             31 A4 02 00 07 00 00 00 84 00 00 00 00 00 00 00 32 30 32 31 3A 30 36 3A
+             30 31 20 31 30 3A 32 30 3A 33 30 00 31 32 33 34 35 36 00 00 04 00 01 00
+             02 00 02 00 00 00 4E 00 00 00 02 00 05 00 03 00 00 00 C2 00 00 00 03 00
+             02 00 02 00 00 00 57 00 00 00 04 00 05 00 03 00 00 00 DA 00 00 00 00 00
+             00 00 30 00 00 00 01 00 00 00 33 00 00 00 01 00 00 00 B8 0B 00 00 64 00
+             00 00 02 00 00 00 01 00 00 00 11 00 00 00 01 00 00 00 BE 0F 00 00 64 00
+             00 00"
+        );
+        let tiff = Tiff::new(&data).unwrap();
+        let metadata = exif_metadata(&tiff);
+        assert_eq!(metadata.len(), 5);
+        assert_eq!(metadata[0], ("make", String::from("Canon")));
+        assert_eq!(metadata[1], ("model", String::from("Canon EOS 5D")));
+        assert_eq!(
+            metadata[2],
+            ("datetime_original", String::from("2021:06:01 10:20:30"))
+        );
+        assert_eq!(metadata[3], ("body_serial", String::from("123456")));
+        assert_eq!(metadata[4], ("gps", String::from("48.858333,-2.294528")));
+    }
+
+    #[test]
+    fn bigtiff() {
+        // BigTIFF with a strip, and a sub-IFD with another strip, followed by other data
+        let mut data = hex!(
+            "49 49 2B 00 08 00 00 00 28 00 00 00 00 00 00 00 55 55 55 55 55 55 55 55
+             55 55 55 55 55 55 55 55 66 66 66 66 66 66 66 66 05 00 00 00 00 00 00 00
+             00 01 03 00 01 00 00 00 00 00 00 00 0A 00 00 00 00 00 00 00 0F 01 02 00
+             05 00 00 00 00 00 00 00 53 4F 4E 59 00 00 00 00 11 01 10 00 01 00 00 00
+             00 00 00 00 10 00 00 00 00 00 00 00 17 01 10 00 01 00 00 00 00 00 00 00
+             10 00 00 00 00 00 00 00 4A 01 10 00 01 00 00 00 00 00 00 00 9C 00 00 00
+             00 00 00 00 00 00 00 00 00 00 00 00 02 00 00 00 00 00 00 00 11 01 10 00
+             01 00 00 00 00 00 00 00 20 00 00 00 00 00 00 00 17 01 10 00 01 00 00 00
+             00 00 00 00 08 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
+        )
+        .to_vec();
+        data.extend([0xAA; 20]);
+
+        let mut tiff = TIFF::default();
+        tiff.deserialize(&mut Cursor::new(data.as_slice())).unwrap();
+        assert!(tiff.is_genuine());
+        assert_eq!(tiff.size(), 212);
+        assert_eq!(tiff.nb_ifds, 2);
+        assert_eq!(tiff.subtype(), Some(ARW));
+
+        // no image width in the first IFD
+        data[48] = 0x01;
+        let mut tiff = TIFF::default();
+        assert!(tiff.deserialize(&mut Cursor::new(data.as_slice())).is_err());
+    }
+}
@@ -0,0 +1,524 @@
+use std::io::{Cursor, Error, ErrorKind, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use memchr::memmem;
+
+use crate::{
+    carvers::{Subtype, fourcc_carver::FourCCCarver},
+    deserializer::Deserializer,
+    err,
+};
+
+// see: https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+
+// record signatures
+const LOCAL_FILE_HEADER: u32 = 0x04034B50;
+const DATA_DESCRIPTOR: u32 = 0x08074B50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014B50;
+const DIGITAL_SIGNATURE: u32 = 0x05054B50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064B50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR: u32 = 0x07064B50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054B50;
+
+// the APK signing block, between the file data and the central directory
+// see: https://source.android.com/docs/security/features/apksigning/v2
+const APK_SIGNING_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+
+// sizes are in the ZIP64 extra field when set to this value
+const ZIP64_SIZE: u32 = 0xFFFFFFFF;
+
+// file types based on ZIP, told by their members
+const DOCX: Subtype = Subtype {
+    ext: "docx",
+    category: "documents/docx",
+};
+const XLSX: Subtype = Subtype {
+    ext: "xlsx",
+    category: "documents/xlsx",
+};
+const PPTX: Subtype = Subtype {
+    ext: "pptx",
+    category: "documents/pptx",
+};
+const ODT: Subtype = Subtype {
+    ext: "odt",
+    category: "documents/odt",
+};
+const ODS: Subtype = Subtype {
+    ext: "ods",
+    category: "documents/ods",
+};
+const ODP: Subtype = Subtype {
+    ext: "odp",
+    category: "documents/odp",
+};
+const EPUB: Subtype = Subtype {
+    ext: "epub",
+    category: "documents/epub",
+};
+const APK: Subtype = Subtype {
+    ext: "apk",
+    category: "executables/apk",
+};
+const JAR: Subtype = Subtype {
+    ext: "jar",
+    category: "executables/jar",
+};
+
+// the file type of the archive, from the names of its members and the content of the mimetype
+// member of ODF and EPUB files
+fn classify(names: &[Vec<u8>], mimetype: Option<&[u8]>) -> Option<Subtype> {
+    let has = |name: &[u8]| names.iter().any(|n| n == name);
+
+    if let Some(mimetype) = mimetype {
+        match mimetype {
+            b"application/vnd.oasis.opendocument.text" => return Some(ODT),
+            b"application/vnd.oasis.opendocument.spreadsheet" => return Some(ODS),
+            b"application/vnd.oasis.opendocument.presentation" => return Some(ODP),
+            b"application/epub+zip" => return Some(EPUB),
+            _ => (),
+        }
+    }
+
+    // APKs are also JARs
+    if has(b"[Content_Types].xml") {
+        if has(b"word/document.xml") {
+            Some(DOCX)
+        } else if has(b"xl/workbook.xml") {
+            Some(XLSX)
+        } else if has(b"ppt/presentation.xml") {
+            Some(PPTX)
+        } else {
+            None
+        }
+    } else if has(b"AndroidManifest.xml") {
+        Some(APK)
+    } else if has(b"META-INF/MANIFEST.MF") {
+        Some(JAR)
+    } else {
+        None
+    }
+}
+
+// move the cursor forward: lengths read from the file could be anything
+fn skip(buffer: &mut Cursor<&[u8]>, len: u64) -> std::io::Result<()> {
+    let Some(position) = buffer.position().checked_add(len) else {
+        return err!(ErrorKind::InvalidData);
+    };
+    buffer.set_position(position);
+    Ok(())
+}
+
+// sizes from the ZIP64 extended information extra field, when set to 0xFFFFFFFF in the header.
+// Also tells whether the field is there
+fn zip64_sizes(extra: &[u8], mut uncompressed: u64, mut compressed: u64) -> (u64, u64, bool) {
+    let mut extra = Cursor::new(extra);
+    let mut zip64 = false;
+
+    while let (Ok(id), Ok(size)) = (
+        extra.read_u16::<LittleEndian>(),
+        extra.read_u16::<LittleEndian>(),
+    ) {
+        let end = extra.position() + size as u64;
+        if id == 0x0001 {
+            zip64 = true;
+            if uncompressed == ZIP64_SIZE as u64 {
+                uncompressed = extra.read_u64::<LittleEndian>().unwrap_or(uncompressed);
+            }
+            if compressed == ZIP64_SIZE as u64 {
+                compressed = extra.read_u64::<LittleEndian>().unwrap_or(compressed);
+            }
+            break;
+        }
+        extra.set_position(end);
+    }
+
+    (uncompressed, compressed, zip64)
+}
+
+// when sizes are not known when writing the local header, they're written after the data in a
+// data descriptor, optionally starting with its signature. Returns the data length and the
+// descriptor length
+fn find_data_descriptor(data: &[u8], zip64: bool) -> Option<(u64, u64)> {
+    let size_len = if zip64 { 8 } else { 4 };
+    let read_size = |pos: usize| -> Option<u64> {
+        let bytes = data.get(pos..pos + size_len)?;
+        Some(bytes.iter().rev().fold(0, |acc, b| (acc << 8) | *b as u64))
+    };
+
+    for pos in memmem::find_iter(data, b"PK") {
+        let signature = data.get(pos..pos + 4)?;
+
+        // descriptor with a signature: the compressed size matches the data length
+        if signature == DATA_DESCRIPTOR.to_le_bytes() && read_size(pos + 8) == Some(pos as u64) {
+            return Some((pos as u64, 8 + 2 * size_len as u64));
+        }
+
+        // descriptor without a signature, followed by the next record
+        let descriptor_len = 4 + 2 * size_len;
+        if (signature == LOCAL_FILE_HEADER.to_le_bytes()
+            || signature == CENTRAL_DIRECTORY_HEADER.to_le_bytes())
+            && pos >= descriptor_len
+            && read_size(pos - descriptor_len + 4) == Some((pos - descriptor_len) as u64)
+        {
+            return Some(((pos - descriptor_len) as u64, descriptor_len as u64));
+        }
+    }
+
+    None
+}
+
+// the ZIP signature, all records are read as chunks
+#[derive(Debug, Default)]
+pub struct ZipHeader {
+    signature: u32,
+}
+
+impl Deserializer for ZipHeader {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        self.signature = buffer.read_u32::<LittleEndian>()?;
+        if self.signature != LOCAL_FILE_HEADER {
+            return err!(ErrorKind::InvalidData);
+        }
+        buffer.set_position(0);
+
+        Ok(0)
+    }
+}
+
+// a record of the ZIP file
+#[derive(Debug, Default)]
+pub struct ZipRecord {
+    signature: u32,
+    subtype: Option<Subtype>, // found in the end of central directory record
+    max_size: Option<usize>,  // where to stop looking for a data descriptor
+}
+
+impl ZipRecord {
+    // local file header, followed by the file data
+    fn local_file(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<()> {
+        buffer.set_position(buffer.position() + 2);
+        let flags = buffer.read_u16::<LittleEndian>()?;
+        buffer.set_position(buffer.position() + 10);
+        let compressed = buffer.read_u32::<LittleEndian>()? as u64;
+        let uncompressed = buffer.read_u32::<LittleEndian>()? as u64;
+        let name_len = buffer.read_u16::<LittleEndian>()? as u64;
+        let extra_len = buffer.read_u16::<LittleEndian>()? as u64;
+
+        buffer.set_position(buffer.position() + name_len);
+        let mut extra = vec![0; extra_len as usize];
+        buffer.read_exact(&mut extra)?;
+        let (_, compressed, zip64) = zip64_sizes(&extra, uncompressed, compressed);
+
+        // sizes are in the data descriptor following the data, which is looked for up to the
+        // maximum size: the file is cut there if it's not found
+        if flags & 0x08 != 0 && compressed == 0 {
+            let start = buffer.position() as usize;
+            let data = buffer.get_ref();
+            let end = self.max_size.map_or(data.len(), |max| max.min(data.len()));
+            match data
+                .get(start..end)
+                .and_then(|data| find_data_descriptor(data, zip64))
+            {
+                Some((length, descriptor_len)) => {
+                    buffer.set_position(start as u64 + length + descriptor_len)
+                }
+                None if end < data.len() => buffer.set_position(end as u64 + 1),
+                None => return err!(ErrorKind::InvalidData),
+            }
+        } else {
+            skip(buffer, compressed)?;
+
+            // the data descriptor is still there, even if sizes are known
+            if flags & 0x08 != 0 {
+                let signature = buffer.read_u32::<LittleEndian>()?;
+                let len = if zip64 { 16 } else { 8 };
+                let len = if signature == DATA_DESCRIPTOR {
+                    len + 4
+                } else {
+                    len
+                };
+                buffer.set_position(buffer.position() + len);
+            }
+        }
+
+        Ok(())
+    }
+
+    // the end of central directory record gives where the central directory is: it must be the one
+    // just read. Member names are read from it to tell the file type
+    fn end_of_central_directory(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<()> {
+        let eocd = buffer.position() - 4;
+        buffer.set_position(buffer.position() + 6);
+        let nb_entries = buffer.read_u16::<LittleEndian>()? as u64;
+        let cd_size = buffer.read_u32::<LittleEndian>()? as u64;
+        let cd_offset = buffer.read_u32::<LittleEndian>()? as u64;
+        let comment_len = buffer.read_u16::<LittleEndian>()? as u64;
+        let end = buffer.position() + comment_len;
+
+        // ZIP64: the values are in the ZIP64 end of central directory record, found by its locator
+        // right before this record
+        let data = *buffer.get_ref();
+        let mut c = Cursor::new(data);
+        c.set_position(eocd.saturating_sub(20));
+        let zip64 = eocd >= 20
+            && c.read_u32::<LittleEndian>().ok() == Some(ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR);
+
+        let (nb_entries, cd_size, cd_offset, cd_end) = if zip64 {
+            c.set_position(c.position() + 4);
+            let record = c.read_u64::<LittleEndian>()?;
+
+            c.set_position(record);
+            if c.read_u32::<LittleEndian>()? != ZIP64_END_OF_CENTRAL_DIRECTORY {
+                return err!(ErrorKind::InvalidData);
+            }
+            c.set_position(c.position() + 28);
+            let nb_entries = c.read_u64::<LittleEndian>()?;
+            let cd_size = c.read_u64::<LittleEndian>()?;
+            let cd_offset = c.read_u64::<LittleEndian>()?;
+            (nb_entries, cd_size, cd_offset, record)
+        } else if cd_offset == ZIP64_SIZE as u64 {
+            return err!(ErrorKind::InvalidData);
+        } else {
+            (nb_entries, cd_size, cd_offset, eocd)
+        };
+
+        // the central directory ends right before the end records, or the digital signature
+        let Some(cd_limit) = cd_offset.checked_add(cd_size) else {
+            return err!(ErrorKind::InvalidData);
+        };
+        let cd_end_ok = cd_limit == cd_end || {
+            let mut c = Cursor::new(data);
+            c.set_position(cd_limit);
+            c.read_u32::<LittleEndian>().ok() == Some(DIGITAL_SIGNATURE)
+        };
+        if !cd_end_ok {
+            return err!(ErrorKind::InvalidData);
+        }
+
+        // walk the central directory to get the member names
+        let mut c = Cursor::new(data);
+        c.set_position(cd_offset);
+        let mut names = Vec::new();
+        let mut mimetype = None;
+        for _ in 0..nb_entries {
+            if c.read_u32::<LittleEndian>()? != CENTRAL_DIRECTORY_HEADER {
+                return err!(ErrorKind::InvalidData);
+            }
+            c.set_position(c.position() + 6);
+            let method = c.read_u16::<LittleEndian>()?;
+            c.set_position(c.position() + 8);
+            let compressed = c.read_u32::<LittleEndian>()? as usize;
+            c.set_position(c.position() + 4);
+            let name_len = c.read_u16::<LittleEndian>()? as u64;
+            let extra_len = c.read_u16::<LittleEndian>()? as u64;
+            let comment_len = c.read_u16::<LittleEndian>()? as u64;
+            c.set_position(c.position() + 8);
+            let local_header = c.read_u32::<LittleEndian>()? as usize;
+
+            let mut name = vec![0; name_len as usize];
+            c.read_exact(&mut name)?;
+            c.set_position(c.position() + extra_len + comment_len);
+
+            // ODF and EPUB start with a stored mimetype member
+            if name == b"mimetype" && method == 0 {
+                mimetype = data
+                    .get(local_header + 26..local_header + 30)
+                    .and_then(|b| {
+                        let start = local_header
+                            + 30
+                            + u16::from_le_bytes([b[0], b[1]]) as usize
+                            + u16::from_le_bytes([b[2], b[3]]) as usize;
+                        data.get(start..start + compressed)
+                    });
+            }
+            names.push(name);
+        }
+
+        self.subtype = classify(&names, mimetype);
+        buffer.set_position(end);
+
+        Ok(())
+    }
+}
+
+impl Deserializer for ZipRecord {
+    fn deserialize(&mut self, buffer: &mut Cursor<&[u8]>) -> std::io::Result<usize> {
+        let start = buffer.position();
+        self.signature = buffer.read_u32::<LittleEndian>()?;
+
+        match self.signature {
+            LOCAL_FILE_HEADER => self.local_file(buffer)?,
+            CENTRAL_DIRECTORY_HEADER => {
+                buffer.set_position(buffer.position() + 24);
+                let name_len = buffer.read_u16::<LittleEndian>()? as u64;
+                let extra_len = buffer.read_u16::<LittleEndian>()? as u64;
+                let comment_len = buffer.read_u16::<LittleEndian>()? as u64;
+                buffer.set_position(buffer.position() + 12 + name_len + extra_len + comment_len);
+            }
+            DIGITAL_SIGNATURE => {
+                let size = buffer.read_u16::<LittleEndian>()? as u64;
+                skip(buffer, size)?;
+            }
+            ZIP64_END_OF_CENTRAL_DIRECTORY => {
+                let size = buffer.read_u64::<LittleEndian>()?;
+                skip(buffer, size)?;
+            }
+            ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR => {
+                buffer.set_position(buffer.position() + 16);
+            }
+            END_OF_CENTRAL_DIRECTORY => self.end_of_central_directory(buffer)?,
+
+            // the APK signing block has no signature at its start, but its size and its magic at
+            // its end
+            _ => {
+                buffer.set_position(start);
+                let size = buffer.read_u64::<LittleEndian>()?;
+                let Some(end) = (start + 8).checked_add(size) else {
+                    return err!(ErrorKind::InvalidData);
+                };
+                let magic_start = end.saturating_sub(16) as usize;
+                if buffer.get_ref().get(magic_start..magic_start + 16)
+                    != Some(APK_SIGNING_BLOCK_MAGIC.as_slice())
+                {
+                    return err!(ErrorKind::InvalidData);
+                }
+                buffer.set_position(end);
+            }
+        }
+
+        Ok((buffer.position() - start) as usize)
+    }
+}
+
+impl FourCCCarver for ZipRecord {
+    fn is_end(&self) -> bool {
+        self.signature == END_OF_CENTRAL_DIRECTORY
+    }
+
+    fn subtype(&self) -> Option<Subtype> {
+        self.subtype
+    }
+
+    // look for the next local file header or central directory header
+    fn resync(buffer: &[u8]) -> Option<usize> {
+        buffer.windows(4).position(|w| {
+            w == LOCAL_FILE_HEADER.to_le_bytes() || w == CENTRAL_DIRECTORY_HEADER.to_le_bytes()
+        })
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = Some(max_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn zip64() {
+        // a stored member, the central directory, then the ZIP64 end records
+        let data = hex!(
+            "50 4B 03 04 14 00 00 00 00 00 00 00 21 50 67 BA 8E EB 03 00 00 00 03 00
+             00 00 13 00 00 00 41 6E 64 72 6F 69 64 4D 61 6E 69 66 65 73 74 2E 78 6D
+             6C 78 79 7A 50 4B 01 02 14 03 14 00 00 00 00 00 00 00 21 50 67 BA 8E EB
+             03 00 00 00 03 00 00 00 13 00 00 00 00 00 00 00 00 00 00 00 80 01 00 00
+             00 00 41 6E 64 72 6F 69 64 4D 61 6E 69 66 65 73 74 2E 78 6D 6C 50 4B 06
+             06 2C 00 00 00 00 00 00 00 2D 00 2D 00 00 00 00 00 00 00 00 00 01 00 00
+             00 00 00 00 00 01 00 00 00 00 00 00 00 41 00 00 00 00 00 00 00 34 00 00
+             00 00 00 00 00 50 4B 06 07 00 00 00 00 75 00 00 00 00 00 00 00 01 00 00
+             00 50 4B 05 06 00 00 00 00 01 00 01 00 41 00 00 00 34 00 00 00 00 00"
+        );
+        let mut c = Cursor::new(data.as_slice());
+        ZipHeader::default().deserialize(&mut c).unwrap();
+
+        let mut signatures = Vec::new();
+        loop {
+            let mut record = ZipRecord::default();
+            record.deserialize(&mut c).unwrap();
+            signatures.push(record.signature);
+            if record.is_end() {
+                assert_eq!(record.subtype(), Some(APK));
+                break;
+            }
+        }
+        assert_eq!(c.position() as usize, data.len());
+        assert_eq!(
+            signatures,
+            [
+                LOCAL_FILE_HEADER,
+                CENTRAL_DIRECTORY_HEADER,
+                ZIP64_END_OF_CENTRAL_DIRECTORY,
+                ZIP64_END_OF_CENTRAL_DIRECTORY_LOCATOR,
+                END_OF_CENTRAL_DIRECTORY
+            ]
+        );
+
+        // the central directory is not where the end record tells
+        let mut data = data;
+        data[0xA5] = 0x35;
+        let mut c = Cursor::new(&data[0xC9..]);
+        assert!(ZipRecord::default().deserialize(&mut c).is_err());
+    }
+
+    #[test]
+    fn data_descriptor() {
+        // with a signature
+        let data = hex!("01 02 03 50 4B 07 08 AA AA AA AA 03 00 00 00 03 00 00 00");
+        assert_eq!(find_data_descriptor(&data, false), Some((3, 16)));
+
+        // without a signature, followed by the next member
+        let data = hex!("01 02 03 AA AA AA AA 03 00 00 00 03 00 00 00 50 4B 03 04");
+        assert_eq!(find_data_descriptor(&data, false), Some((3, 12)));
+
+        let data = hex!("01 02 03 AA AA AA AA 04 00 00 00 03 00 00 00 50 4B 03 04");
+        assert_eq!(find_data_descriptor(&data, false), None);
+
+        // a member named "a", whose descriptor is only looked for up to the maximum size
+        let data = hex!(
+            "50 4B 03 04 14 00 08 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00
+             00 00 01 00 00 00 61 01 02 03 50 4B 07 08 AA AA AA AA 03 00 00 00 03 00
+             00 00"
+        );
+        let mut record = ZipRecord::default();
+        assert_eq!(record.deserialize(&mut Cursor::new(&data)).unwrap(), 50);
+        record.set_max_size(40);
+        assert_eq!(record.deserialize(&mut Cursor::new(&data)).unwrap(), 41);
+
+        // sizes which overflow
+        let data = hex!("50 4B 06 06 FF FF FF FF FF FF FF FF");
+        let mut record = ZipRecord::default();
+        assert_eq!(
+            record
+                .deserialize(&mut Cursor::new(data.as_slice()))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn classify_members() {
+        let names = |list: &[&str]| -> Vec<Vec<u8>> {
+            list.iter().map(|n| n.as_bytes().to_vec()).collect()
+        };
+
+        let ooxml = names(&["[Content_Types].xml", "_rels/.rels", "xl/workbook.xml"]);
+        assert_eq!(classify(&ooxml, None), Some(XLSX));
+
+        let apk = names(&["META-INF/MANIFEST.MF", "AndroidManifest.xml"]);
+        assert_eq!(classify(&apk, None), Some(APK));
+
+        let jar = names(&["META-INF/MANIFEST.MF", "Main.class"]);
+        assert_eq!(classify(&jar, None), Some(JAR));
+
+        let odf = names(&["mimetype", "content.xml"]);
+        let mimetype = b"application/vnd.oasis.opendocument.spreadsheet".as_slice();
+        assert_eq!(classify(&odf, Some(mimetype)), Some(ODS));
+
+        assert_eq!(classify(&names(&["a.txt"]), None), None);
+    }
+}
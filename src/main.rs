@@ -1,13 +1,12 @@
 use std::{
     fs::File,
-    ops::Range,
-    sync::{Arc, Mutex, atomic::AtomicUsize},
+    sync::{Arc, atomic::AtomicUsize},
     thread,
     time::Instant,
 };
 
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use log::{debug, info, trace};
+use log::{info, trace};
 use memmap2::MmapOptions;
 
 mod args;
@@ -16,7 +15,7 @@ use args::CliOptions;
 mod carvers;
 
 mod search;
-use search::Context;
+use search::{Context, Hit, chunk_bounds};
 
 mod filetypes;
 use filetypes::corpus::Corpus;
@@ -26,6 +25,8 @@ mod deserializer;
 mod audit;
 use audit::AuditFile;
 
+mod validation;
+
 fn main() -> anyhow::Result<()> {
     // harvest cli arguments
     let opts = CliOptions::new()?;
@@ -38,13 +39,17 @@ fn main() -> anyhow::Result<()> {
     let mmap = Arc::new(mmap);
 
     // create audit file
-    let mut ad = AuditFile::new()?;
-    ad.add_metadata(&opts.input_file, mmap.len())?;
-    let audit_file = Arc::new(Mutex::new(ad));
+    let mut audit_file = AuditFile::new()?;
+    audit_file.add_metadata(&opts.input_file, mmap.len())?;
 
     // build our patterns and optionally retain only file types that are passed in the cli
     let mut corpus = Corpus::new(opts.min_size);
+    corpus.set_limits(&opts.type_limits)?;
+    corpus.set_max_sizes(opts.max_size, &opts.type_max_sizes)?;
     corpus.retain(&opts.ext_list);
+    if let Some(method) = opts.carving_method {
+        corpus.set_carving_method(method);
+    }
 
     let corpus = Arc::new(corpus);
 
@@ -52,85 +57,110 @@ fn main() -> anyhow::Result<()> {
     let nb_files = Arc::new(AtomicUsize::new(0));
 
     // build patterns and aho-corasick engine
-    let ac = Arc::new(corpus.patterns()?);
+    let patterns = Arc::new(corpus.patterns()?);
+
+    // a magic starting in the last bytes of a chunk spills over the next one: each thread
+    // scans a bit past its own chunk to catch it
+    let overlap = corpus.max_magic_len().saturating_sub(1);
 
     // create a MultiProgress object to manage multiple progress bars
     let multi_progress = Arc::new(MultiProgress::new());
 
     // compute the different chunk according to the number of threads
     // mmap is divided in several nb_threads chunks, each chunk is provided to a thread
+    // which owns all the patterns starting inside its chunk
     let mut handles = vec![];
-    let chunk_size = mmap.len() / opts.nb_threads;
 
     for i in 0..opts.nb_threads {
         // clone what is needed
         let mmap_clone = Arc::clone(&mmap); // Clone Arc for each thread
         let multi_progress_clone = Arc::clone(&multi_progress);
-        let ac_clone = Arc::clone(&ac);
+        let patterns_clone = Arc::clone(&patterns);
         let corpus_clone = Arc::clone(&corpus);
         let nb_files_clone = Arc::clone(&nb_files);
-        let audit_file_clone = Arc::clone(&audit_file);
 
         // spawn thread
-        let handle = thread::spawn(move || -> anyhow::Result<usize> {
+        let handle = thread::spawn(move || -> anyhow::Result<Vec<Hit>> {
             info!("starting thread {}", i);
 
-            // calculate the buffer offsets
-            let start = i * chunk_size;
-            let end = if i == opts.nb_threads - 1 {
-                mmap_clone.len()
-            } else {
-                start + chunk_size
-            };
-
             // we pass the range to the search function
-            let rg = Range { start, end };
+            let rg = chunk_bounds(mmap_clone.len(), opts.nb_threads, i);
 
             // define a progress bar dedicated to this thread
-            let pb = multi_pbar(&multi_progress_clone, end - start, i);
+            let pb = multi_pbar(&multi_progress_clone, rg.len(), i);
             pb.set_message("Searching..................");
 
             // now search within each chunk
             let mut ctx = Context {
                 mmap: &mmap_clone,
                 bounds: rg,
+                overlap,
                 pb: &pb,
-                ac: &ac_clone,
+                patterns: &patterns_clone,
                 corpus: &corpus_clone,
+                naming: opts.naming,
+                embedded: opts.embedded,
+                oversize: opts.oversize,
+                validate: opts.validate,
                 nb_files: &nb_files_clone,
-                audit_file: &audit_file_clone,
             };
 
-            let found = ctx.search(&opts.limit)?;
+            let hits = ctx.search(&opts.limit)?;
+            let found = hits.iter().filter(|hit| hit.artefact.is_some()).count();
 
             // end of thread
             pb.set_message(format!("thread finished, {} files found", found));
             pb.finish();
 
-            Ok(found)
+            Ok(hits)
         });
 
         handles.push(handle);
     }
 
     // Wait for all threads to complete
-    let mut thread_id = 0;
-    let mut total_count = 0usize;
+    let mut hits = Vec::new();
 
-    for handle in handles {
+    for (thread_id, handle) in handles.into_iter().enumerate() {
         match handle.join() {
             Ok(res) => match res {
-                Ok(count) => total_count += count,
-                Err(e) => info!("Thread {} finished successfully!", thread_id),
+                Ok(thread_hits) => hits.extend(thread_hits),
+                Err(e) => info!("Thread {} finished with error: {}", thread_id, e),
             },
             Err(_) => info!("Thread {} panicked!", thread_id),
         }
-        thread_id += 1;
     }
 
+    // merge what all threads found
+    let pb = ProgressBar::hidden();
+    let ctx = Context {
+        mmap: &mmap,
+        bounds: 0..mmap.len(),
+        overlap: 0,
+        pb: &pb,
+        patterns: &patterns,
+        corpus: &corpus,
+        naming: opts.naming,
+        embedded: opts.embedded,
+        oversize: opts.oversize,
+        validate: opts.validate,
+        nb_files: &nb_files,
+    };
+    let artefacts = ctx.reconcile(hits, &opts.limit)?;
+    let total_count = artefacts.len();
+
+    // audit is written once all threads are done
+    for ad in artefacts {
+        audit_file.add_artefact(ad);
+    }
+    audit_file.close()?;
+
     // print out statistics
     let elapsed = now.elapsed();
-    println!("total time: {:?}, total number of artefacts: {}", elapsed, total_count);
+    println!(
+        "total time: {:?}, total number of artefacts: {}",
+        elapsed, total_count
+    );
 
     Ok(())
 }
@@ -2,57 +2,191 @@
 // a segment is made of a starting and ending offset
 
 use std::{
+    fs,
     ops::Range,
-    sync::{
-        Mutex,
-        atomic::{AtomicUsize, Ordering},
-    },
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use crate::{
-    audit::{AuditData, AuditFile},
-    filetypes::corpus::Corpus,
+    audit::AuditData,
+    filetypes::corpus::{Corpus, FileNaming, FileType, Patterns, reserve},
 };
 
-use aho_corasick::AhoCorasick;
 use indicatif::ProgressBar;
 use log::{debug, info, trace};
 
+// what to do with patterns found inside an artefact already carved (e.g.: an EXIF thumbnail
+// inside a JPEG)
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum EmbeddedPolicy {
+    #[default]
+    Skip, // don't carve them
+    Child, // carve them, and record them as children of the artefact containing them
+}
+
+// what to do with artefacts bigger than the maximum size of their file type
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OversizePolicy {
+    #[default]
+    Drop, // don't carve them
+    Truncate, // only save the first bytes, up to the maximum size
+}
+
+// a pattern found in the image, and the artefact carved from it if any
+#[derive(Debug)]
+pub struct Hit {
+    pub offset: usize,               // absolute offset of the pattern
+    pub pat_index: usize,            // index of the file type in the corpus
+    pub artefact: Option<AuditData>, // None if the pattern was skipped
+}
+
 #[derive(Debug)]
 pub struct Context<'a> {
-    pub mmap: &'a [u8],                   // the mmap to search
-    pub bounds: Range<usize>,             // contains the bounds of the chunk to search
-    pub pb: &'a ProgressBar,              // ref on progress bar
-    pub ac: &'a AhoCorasick,              // ref on Aho-Corasick engine
-    pub corpus: &'a Corpus,               // ref on global corpus
-    pub nb_files: &'a AtomicUsize,        // ref on the global number of file currently carved out
-    pub audit_file: &'a Mutex<AuditFile>, // ref on audit file
+    pub mmap: &'a [u8],            // the mmap to search
+    pub bounds: Range<usize>,      // contains the bounds of the chunk owned by this thread
+    pub overlap: usize,            // number of bytes scanned past the chunk end
+    pub pb: &'a ProgressBar,       // ref on progress bar
+    pub patterns: &'a Patterns,    // ref on Aho-Corasick engine
+    pub corpus: &'a Corpus,        // ref on global corpus
+    pub naming: FileNaming,        // how carved files are named
+    pub embedded: EmbeddedPolicy,  // what to do with patterns inside carved artefacts
+    pub oversize: OversizePolicy,  // what to do with artefacts bigger than their maximum size
+    pub validate: bool,            // true to check the content of carved artefacts
+    pub nb_files: &'a AtomicUsize, // ref on the global number of file currently carved out
 }
 
-impl<'a> Context<'a> {
-    // add 1 to the global number of file currently carved out
-    /*     pub fn one_more(&mut self) {
-        self.nb_files.fetch_add(1, Ordering::Relaxed);
-    } */
+impl Context<'_> {
+    // book one more file to carve, both globally and for the file type. Returns false if one of
+    // the limits is reached
+    fn reserve(&self, ft: &FileType, limit: &Option<usize>) -> bool {
+        let booked = match limit {
+            Some(limit) => reserve(self.nb_files, *limit),
+            None => {
+                self.nb_files.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+        };
+
+        if booked && !ft.reserve() {
+            self.nb_files.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+
+        booked
+    }
+
+    // true if the global limit is reached: all threads must stop
+    fn is_done(&self, limit: &Option<usize>) -> bool {
+        limit.is_some_and(|limit| self.nb_files.load(Ordering::Relaxed) >= limit)
+    }
+
+    // the window is the chunk extended by the overlap, so that a magic starting at the very end
+    // of the chunk is fully visible. Patterns starting in the overlap belong to the next chunk.
+    fn window(&self) -> Range<usize> {
+        let end = (self.bounds.end + self.overlap).min(self.mmap.len());
+        self.bounds.start..end
+    }
+
+    // try to carve and save an artefact of type ft at the offset given
+    fn carve(
+        &self,
+        offset: usize,
+        ft: &FileType,
+        limit: &Option<usize>,
+    ) -> anyhow::Result<Option<AuditData>> {
+        // the carving method of the file type tells how to carve it
+        let result = ft.carve(&self.mmap[offset..])?;
+
+        // offset returned is 0, we didn't find/carve any artefact
+        if result.offset == 0 {
+            return Ok(None);
+        }
+
+        // too big: it's only saved if we accept truncated files
+        if result.truncated && self.oversize == OversizePolicy::Drop {
+            debug!(
+                "dropping {} artefact at offset 0x{:X?}: too big",
+                ft.ext, offset
+            );
+            return Ok(None);
+        }
+
+        // stop carving if we reached the limit, before saving anything
+        if !self.reserve(ft, limit) {
+            return Ok(None);
+        }
+
+        // now we can save the artefact
+        let file_name = ft.file_name(offset as u64, &self.naming, result.subtype.as_ref());
+        let payload = &self.mmap[offset..offset + result.length];
+        ft.save_file(payload, &file_name)?;
+
+        // check the content of the artefact if the file type allows it
+        let mut metadata = result.metadata;
+        let validation = ft
+            .validation_func
+            .filter(|_| self.validate)
+            .map(|validation_func| validation_func(payload, &mut metadata));
+
+        info!(
+            "found and carved artefact ({}) at offsets: 0x{:X?}-0x{:X?}",
+            file_name,
+            offset,
+            offset as u64 + result.offset
+        );
+
+        Ok(Some(AuditData {
+            artefact: file_name,
+            offset_start: offset as u64,
+            offset_end: offset as u64 + result.offset,
+            length: result.length as u64,
+            truncated: result.truncated,
+            validation,
+            gaps: result
+                .gaps
+                .iter()
+                .map(|gap| offset as u64 + gap.start..offset as u64 + gap.end)
+                .collect(),
+            embedded: result
+                .embedded
+                .iter()
+                .map(|e| offset as u64 + e.start..offset as u64 + e.end)
+                .collect(),
+            metadata,
+            parent: None,
+        }))
+    }
 
     // try to carve file using the context. This means trying out all patterns using the Aho-Corasick
     // algorithm to get potential file signatures, and call the carving function to try to carve
-    pub fn search(&mut self, limit: &Option<usize>) -> anyhow::Result<usize> {
+    pub fn search(&mut self, limit: &Option<usize>) -> anyhow::Result<Vec<Hit>> {
         trace!("bounds={:?}", self.bounds);
 
         // loop through bytes trying to discover some patterns
         let absolute_offset = self.bounds.start;
 
-        // we count the number of files found for each thread
-        let mut files_found = 0usize;
+        // all patterns carved or skipped by this thread
+        let mut hits = Vec::new();
+
+        // end of the artefacts carved so far, overall and per file type
+        let mut covered_until = 0usize;
+        let mut covered_by_type = vec![0usize; self.corpus.len()];
 
-        // we're searching patterns inside this chunk/segment
-        let chunk = &self.mmap[self.bounds.clone()];
+        // we're searching patterns inside this chunk/segment, extended by the overlap
+        let chunk = &self.mmap[self.window()];
+        let chunk_len = self.bounds.len();
 
         // loop through the pattern we found
         // a found pattern doesn't mean it's a genuine file. It's a potentialty
-        for mat in self.ac.find_iter(chunk) {
-            let pat_index = mat.pattern().as_usize();
+        // overlapping matches are reported so that what's found doesn't depend on where the
+        // chunk starts
+        for mat in self.patterns.ac.find_overlapping_iter(chunk) {
+            // this one will be found by the thread owning the next chunk
+            if mat.start() >= chunk_len {
+                continue;
+            }
+
+            let pat_index = self.patterns.index_of(mat.pattern().as_usize());
             let pat = &self.corpus.get(pat_index).unwrap().ext;
             debug!(
                 "Found pattern '{}' at offset 0x{:X?}({})",
@@ -62,13 +196,7 @@ impl<'a> Context<'a> {
             );
 
             // pattern returned contains the index of the pattern inside the corpus
-            let ft = self
-                .corpus
-                .get(mat.pattern().as_usize())
-                .expect("error getting magic");
-
-            // the carving func is the function used to try to carve for a specific file type
-            let carving_func = ft.carving_func;
+            let ft = self.corpus.get(pat_index).expect("error getting magic");
 
             // absolute_found_offset is the pattern offset found for the while file
             let absolute_found_offset = absolute_offset + mat.start();
@@ -76,90 +204,550 @@ impl<'a> Context<'a> {
             // mat.start() is the pattern offset found for the chunk
             self.pb.set_position(mat.start() as u64);
 
-            // let ft = Arc::new(ft);
-            // println!("starting carving at offset: {}", absolute_found_offset);
-            let result = carving_func(&self.mmap[absolute_found_offset..], ft)?;
-
-            // offset returned is 0, we didn't find/carve any artefact
-            if result.offset == 0 {
+            // this pattern is part of an artefact we already carved: keep track of it in case
+            // this artefact is later found to be inside an artefact carved by another thread
+            // a magic repeated in the artefact is never a nested file
+            // once a limit is reached, patterns are only recorded: the artefacts finally kept
+            // could be others than the ones carved by this thread
+            if (self.embedded == EmbeddedPolicy::Skip && absolute_found_offset < covered_until)
+                || (ft.repeated_magic && absolute_found_offset < covered_by_type[pat_index])
+                || self.is_done(limit)
+                || ft.is_full()
+            {
+                hits.push(Hit {
+                    offset: absolute_found_offset,
+                    pat_index,
+                    artefact: None,
+                });
                 continue;
             }
 
-            // update progress bar with the file name being carved
-            let file_name = result.file_name.unwrap();
-            info!(
-                "found and carved artefact ({}) at offsets: 0x{:X?}-0x{:X?}",
-                file_name,
-                absolute_found_offset,
-                absolute_found_offset as u64 + result.offset
-            );
-
-            // save audit data
-            let ad = AuditData {
-                artefact: &file_name.as_str(),
-                offset_start: absolute_found_offset as u64,
-                offset_end: absolute_found_offset as u64 + result.offset,
-                length: result.length as u64,
+            let Some(ad) = self.carve(absolute_found_offset, ft, limit)? else {
+                continue;
             };
-            {
-                if let Ok(mut ul) = self.audit_file.try_lock() {
-                    ul.add_artefact(&ad)?;
+            covered_until = covered_until.max(ad.offset_end as usize);
+            covered_by_type[pat_index] = covered_by_type[pat_index].max(ad.offset_end as usize);
+
+            // print out file name on progress bar
+            self.pb.set_message(ad.artefact.clone());
+
+            hits.push(Hit {
+                offset: absolute_found_offset,
+                pat_index,
+                artefact: Some(ad),
+            });
+        }
+
+        Ok(hits)
+    }
+
+    // keep an artefact carved by a thread if the limits allow it, remove it otherwise
+    fn keep(
+        &self,
+        ad: AuditData,
+        ft: &FileType,
+        limit: &Option<usize>,
+    ) -> anyhow::Result<Option<AuditData>> {
+        if self.reserve(ft, limit) {
+            return Ok(Some(ad));
+        }
+
+        debug!("removing artefact {} over the limit", ad.artefact);
+        fs::remove_file(&ad.artefact)?;
+        Ok(None)
+    }
+
+    // carve a pattern skipped by a thread, if the limits allow it
+    fn carve_skipped(
+        &self,
+        offset: usize,
+        ft: &FileType,
+        limit: &Option<usize>,
+    ) -> anyhow::Result<Option<AuditData>> {
+        if self.is_done(limit) || ft.is_full() {
+            return Ok(None);
+        }
+        self.carve(offset, ft, limit)
+    }
+
+    // gather the patterns found by all threads and decide what to keep. A thread doesn't know
+    // about artefacts carved by the previous threads and spilling over its own chunk, so the
+    // decisions made by each thread are reviewed here in offset order, to get the same result
+    // whatever the number of threads
+    pub fn reconcile(
+        &self,
+        mut hits: Vec<Hit>,
+        limit: &Option<usize>,
+    ) -> anyhow::Result<Vec<AuditData>> {
+        hits.sort_by_key(|hit| (hit.offset, hit.pat_index));
+
+        let mut artefacts: Vec<AuditData> = Vec::new();
+
+        // the artefacts are counted again in offset order: the limits keep the first ones
+        self.nb_files.store(0, Ordering::Relaxed);
+        for ft in self.corpus.iter() {
+            ft.nb_files.store(0, Ordering::Relaxed);
+        }
+
+        match self.embedded {
+            EmbeddedPolicy::Skip => {
+                let mut covered_until = 0usize;
+
+                for hit in hits {
+                    let ft = self.corpus.get(hit.pat_index).expect("error getting magic");
+                    let covered = hit.offset < covered_until;
+
+                    let ad = match hit.artefact {
+                        // carved but it's inside an artefact carved by a previous thread
+                        Some(ad) if covered => {
+                            debug!("removing embedded artefact {}", ad.artefact);
+                            fs::remove_file(&ad.artefact)?;
+                            continue;
+                        }
+                        Some(ad) => match self.keep(ad, ft, limit)? {
+                            Some(ad) => ad,
+                            None => continue,
+                        },
+
+                        // skipped because of an artefact which is finally not kept, or because
+                        // of the limits
+                        None if !covered => match self.carve_skipped(hit.offset, ft, limit)? {
+                            Some(ad) => ad,
+                            None => continue,
+                        },
+                        None => continue,
+                    };
+
+                    covered_until = covered_until.max(ad.offset_end as usize);
+                    artefacts.push(ad);
                 }
             }
+            EmbeddedPolicy::Child => {
+                // stack of artefacts containing the current one, with their file type
+                let mut containers: Vec<(u64, String, usize)> = Vec::new();
 
-            // print out file name on progress bar
-            self.pb.set_message(file_name);
+                for hit in hits {
+                    while containers
+                        .last()
+                        .is_some_and(|(end, _, _)| *end <= hit.offset as u64)
+                    {
+                        containers.pop();
+                    }
+
+                    // a magic repeated in an artefact of the same type
+                    let ft = self.corpus.get(hit.pat_index).expect("error getting magic");
+                    let repeated = ft.repeated_magic
+                        && containers
+                            .iter()
+                            .any(|(_, _, pat_index)| *pat_index == hit.pat_index);
 
-            // stop carving is we reached the limit
-            files_found += 1;
-            if let Some(limit) = limit {
-                if files_found > *limit {
-                    return Ok(files_found);
+                    let mut ad = match hit.artefact {
+                        // carved by a thread starting in the middle of an artefact of the same type
+                        Some(ad) if repeated => {
+                            debug!("removing repeated artefact {}", ad.artefact);
+                            fs::remove_file(&ad.artefact)?;
+                            continue;
+                        }
+                        Some(ad) => match self.keep(ad, ft, limit)? {
+                            Some(ad) => ad,
+                            None => continue,
+                        },
+
+                        // skipped because of the limits
+                        None if !repeated => match self.carve_skipped(hit.offset, ft, limit)? {
+                            Some(ad) => ad,
+                            None => continue,
+                        },
+                        None => continue,
+                    };
+
+                    ad.parent = containers.last().map(|(_, name, _)| name.clone());
+                    containers.push((ad.offset_end, ad.artefact.clone(), hit.pat_index));
+                    artefacts.push(ad);
                 }
             }
         }
 
-        Ok(files_found)
+        Ok(artefacts)
     }
 }
 
-// fn window(mmap: &[u8], size: u64, offset: u64, buffer_size: usize) -> &[u8] {
-//     let lower = offset as usize;
-//     let upper = if lower + buffer_size < (size as usize) {
-//         lower + buffer_size
-//     } else {
-//         size as usize
-//     };
+// the chunk of the image searched by thread i: the image is divided in nb_threads chunks, the
+// last one taking what's left
+pub fn chunk_bounds(len: usize, nb_threads: usize, i: usize) -> Range<usize> {
+    let chunk_size = len / nb_threads;
+    let start = i * chunk_size;
+    let end = if i == nb_threads - 1 {
+        len
+    } else {
+        start + chunk_size
+    };
+
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        thread,
+    };
 
-//     &mmap[lower..upper]
-// }
+    use super::*;
 
-// #[cfg(test)]
-// mod tests {
+    // a BMP of len bytes
+    fn bmp(len: u32) -> Vec<u8> {
+        let mut bmp = b"BM".to_vec();
+        bmp.extend(len.to_le_bytes());
+        bmp.extend([0; 4]);
+        bmp.extend(54u32.to_le_bytes());
+        bmp.extend(40u32.to_le_bytes());
+        bmp.resize(len as usize, 0x11);
+        bmp
+    }
 
-//     use super::*;
+    // carve the image like main does, in a directory of its own
+    fn carve(
+        image: &[u8],
+        nb_threads: usize,
+        embedded: EmbeddedPolicy,
+        name: &str,
+    ) -> (PathBuf, Vec<AuditData>) {
+        carve_with(
+            image,
+            Corpus::new(0),
+            nb_threads,
+            embedded,
+            OversizePolicy::Drop,
+            None,
+            name,
+        )
+    }
 
-//     fn window_() {
-//         let buf = b"AAAAAAAAAABBBBBBBBBB";
+    // same with the file types, the oversize policy and the limit passed
+    fn carve_with(
+        image: &[u8],
+        mut corpus: Corpus,
+        nb_threads: usize,
+        embedded: EmbeddedPolicy,
+        oversize: OversizePolicy,
+        limit: Option<usize>,
+        name: &str,
+    ) -> (PathBuf, Vec<AuditData>) {
+        let dir = std::env::temp_dir().join(format!(
+            "rodin_{}_{}_{}",
+            name,
+            nb_threads,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
 
-//         let w = window(&buf.as_slice(), buf.len() as u64, 0, 5);
-//         assert_eq!(w.len(), 5);
-//         assert_eq!(w, b"AAAAA");
+        corpus.set_output_dir(&dir);
+        let patterns = corpus.patterns().unwrap();
+        let nb_files = AtomicUsize::new(0);
+        let pb = ProgressBar::hidden();
+        let context = |bounds| Context {
+            mmap: image,
+            bounds,
+            overlap: corpus.max_magic_len() - 1,
+            pb: &pb,
+            patterns: &patterns,
+            corpus: &corpus,
+            naming: FileNaming::Offset,
+            embedded,
+            oversize,
+            validate: false,
+            nb_files: &nb_files,
+        };
 
-//         let w = window(&buf.as_slice(), buf.len() as u64, 5, 10);
-//         assert_eq!(w.len(), 10);
-//         assert_eq!(w, b"AAAAABBBBB");
+        let hits = thread::scope(|s| {
+            let handles: Vec<_> = (0..nb_threads)
+                .map(|i| {
+                    let mut ctx = context(chunk_bounds(image.len(), nb_threads, i));
+                    s.spawn(move || ctx.search(&limit).unwrap())
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        let artefacts = context(0..image.len()).reconcile(hits, &limit).unwrap();
+        assert_eq!(nb_files.load(Ordering::Relaxed), artefacts.len());
 
-//         let w = window(&buf.as_slice(), buf.len() as u64, 10, 10);
-//         assert_eq!(w.len(), 10);
-//         assert_eq!(w, b"BBBBBBBBBB");
+        (dir, artefacts)
+    }
 
-//         let w = window(&buf.as_slice(), buf.len() as u64, 15, 10);
-//         assert_eq!(w.len(), 5);
-//         assert_eq!(w, b"BBBBB");
+    // the artefacts carved and their parents, relative to the directory, after checking they
+    // are the only files left in it
+    fn summary(dir: &Path, artefacts: &[AuditData]) -> Vec<(String, u64, u64, Option<String>)> {
+        let relative = |name: &str| {
+            let path = Path::new(name).strip_prefix(dir).unwrap();
+            path.to_string_lossy().to_string()
+        };
 
-//         let w = window(&buf.as_slice(), buf.len() as u64, 19, 10);
-//         assert_eq!(w.len(), 5);
-//         assert_eq!(w, b"BB");
-//     }
-// }
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                if entry.path().is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    files.push(relative(&entry.path().to_string_lossy()));
+                }
+            }
+        }
+        // nothing carved, nothing created
+        if dir.exists() {
+            fs::remove_dir_all(dir).unwrap();
+        }
+
+        let summary: Vec<_> = artefacts
+            .iter()
+            .map(|ad| {
+                (
+                    relative(&ad.artefact),
+                    ad.offset_start,
+                    ad.offset_end,
+                    ad.parent.as_deref().map(relative),
+                )
+            })
+            .collect();
+
+        let mut carved: Vec<_> = summary.iter().map(|s| s.0.clone()).collect();
+        carved.sort();
+        files.sort();
+        assert_eq!(files, carved);
+
+        summary
+    }
+
+    // an MP3 of frames frames, whose headers are the same magic
+    fn mp3(frames: usize) -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x44];
+        frame.resize(417, 0);
+        frame.repeat(frames)
+    }
+
+    // A holds the start of B, which spills over A and holds C, and an MP3 follows. The thread
+    // chunks start between A and B (2 and 4 threads), and in the middle of the MP3 (3 and 4)
+    fn image() -> Vec<u8> {
+        let mut image = vec![0u8; 24000];
+        image[11200..12200].copy_from_slice(&bmp(1000));
+        image[12100..12500].copy_from_slice(&bmp(400));
+        image[12300..12400].copy_from_slice(&bmp(100));
+        image[15000..23340].copy_from_slice(&mp3(20));
+        image
+    }
+
+    #[test]
+    fn skip_embedded() {
+        let image = image();
+        let (dir, artefacts) = carve(&image, 1, EmbeddedPolicy::Skip, "skip");
+        let expected = summary(&dir, &artefacts);
+        let offsets: Vec<_> = expected.iter().map(|s| (s.1, s.2, s.3.is_some())).collect();
+        assert_eq!(
+            offsets,
+            [
+                (11200, 12200, false),
+                (12300, 12400, false),
+                (15000, 23340, false)
+            ]
+        );
+
+        // B is removed when carved by another thread, and C is carved again
+        for nb_threads in [2, 3, 4] {
+            let (dir, artefacts) = carve(&image, nb_threads, EmbeddedPolicy::Skip, "skip");
+            assert_eq!(summary(&dir, &artefacts), expected);
+        }
+    }
+
+    #[test]
+    fn child_embedded() {
+        let image = image();
+        let (dir, artefacts) = carve(&image, 1, EmbeddedPolicy::Child, "child");
+        let expected = summary(&dir, &artefacts);
+        let offsets: Vec<_> = expected.iter().map(|s| (s.1, s.2)).collect();
+        assert_eq!(
+            offsets,
+            [
+                (11200, 12200),
+                (12100, 12500),
+                (12300, 12400),
+                (15000, 23340)
+            ]
+        );
+        assert_eq!(expected[0].3, None);
+        assert_eq!(expected[1].3.as_ref(), Some(&expected[0].0));
+        assert_eq!(expected[2].3.as_ref(), Some(&expected[1].0));
+        assert_eq!(expected[3].3, None);
+
+        // the MP3 carved from a frame in the middle of the first one is removed
+        for nb_threads in [2, 3, 4] {
+            let (dir, artefacts) = carve(&image, nb_threads, EmbeddedPolicy::Child, "child");
+            assert_eq!(summary(&dir, &artefacts), expected);
+        }
+    }
+
+    #[test]
+    fn straddling_magic() {
+        // "BM" starts on the last byte of the first chunk
+        let mut image = vec![0u8; 1000];
+        image[499..599].copy_from_slice(&bmp(100));
+
+        for nb_threads in [1, 2] {
+            let (dir, artefacts) = carve(&image, nb_threads, EmbeddedPolicy::Skip, "straddling");
+            let summary = summary(&dir, &artefacts);
+            assert_eq!(summary.len(), 1);
+            assert_eq!((summary[0].1, summary[0].2), (499, 599));
+        }
+    }
+
+    #[test]
+    fn oversize() {
+        // a BMP of 400 bytes, and one whose header claims more than what's left of the image
+        let mut image = vec![0u8; 1000];
+        image[100..500].copy_from_slice(&bmp(400));
+        image[600..700].copy_from_slice(&bmp(100));
+        image[602..606].copy_from_slice(&5000u32.to_le_bytes());
+
+        // the second one isn't all there, whatever the policy
+        for oversize in [OversizePolicy::Drop, OversizePolicy::Truncate] {
+            let (dir, artefacts) = carve_with(
+                &image,
+                Corpus::new(0),
+                1,
+                EmbeddedPolicy::Skip,
+                oversize,
+                None,
+                "oversize",
+            );
+            let offsets: Vec<_> = summary(&dir, &artefacts)
+                .iter()
+                .map(|s| (s.1, s.2))
+                .collect();
+            assert_eq!(offsets, [(100, 500)]);
+        }
+
+        // both are over the maximum size, set for all file types or for BMP only
+        for (max_size, max_sizes) in [
+            (Some(300), vec![]),
+            (None, vec![(String::from("bmp"), 300)]),
+        ] {
+            let mut corpus = Corpus::new(0);
+            corpus.set_max_sizes(max_size, &max_sizes).unwrap();
+            let (dir, artefacts) = carve_with(
+                &image,
+                corpus,
+                1,
+                EmbeddedPolicy::Skip,
+                OversizePolicy::Drop,
+                None,
+                "oversize",
+            );
+            assert!(summary(&dir, &artefacts).is_empty());
+
+            let mut corpus = Corpus::new(0);
+            corpus.set_max_sizes(max_size, &max_sizes).unwrap();
+            let (dir, artefacts) = carve_with(
+                &image,
+                corpus,
+                1,
+                EmbeddedPolicy::Skip,
+                OversizePolicy::Truncate,
+                None,
+                "oversize",
+            );
+            assert!(artefacts.iter().all(|ad| ad.truncated));
+            let offsets: Vec<_> = summary(&dir, &artefacts)
+                .iter()
+                .map(|s| (s.1, s.2))
+                .collect();
+            assert_eq!(offsets, [(100, 400), (600, 900)]);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        // 5 BMPs and 2 MP3s
+        let mut image = vec![0u8; 20000];
+        for i in 0..5 {
+            image[1000 * i..1000 * i + 100].copy_from_slice(&bmp(100));
+        }
+        image[6000..10170].copy_from_slice(&mp3(10));
+        image[12000..16170].copy_from_slice(&mp3(10));
+
+        let count = |artefacts: &[AuditData], ext: &str| {
+            artefacts
+                .iter()
+                .filter(|ad| ad.artefact.ends_with(&format!(".{}", ext)))
+                .count()
+        };
+
+        // for all file types, or for BMP only, whatever the number of threads
+        for nb_threads in [1, 2, 4] {
+            let (dir, artefacts) = carve_with(
+                &image,
+                Corpus::new(0),
+                nb_threads,
+                EmbeddedPolicy::Skip,
+                OversizePolicy::Drop,
+                Some(3),
+                "limits",
+            );
+            assert_eq!(summary(&dir, &artefacts).len(), 3);
+
+            let mut corpus = Corpus::new(0);
+            corpus.set_limits(&[(String::from("bmp"), 2)]).unwrap();
+            let (dir, artefacts) = carve_with(
+                &image,
+                corpus,
+                nb_threads,
+                EmbeddedPolicy::Skip,
+                OversizePolicy::Drop,
+                None,
+                "limits",
+            );
+            assert_eq!((count(&artefacts, "bmp"), count(&artefacts, "mp3")), (2, 2));
+            summary(&dir, &artefacts);
+        }
+
+        // not a file type
+        let mut corpus = Corpus::new(0);
+        assert!(corpus.set_limits(&[(String::from("xyz"), 2)]).is_err());
+    }
+
+    #[test]
+    fn limits_in_offset_order() {
+        // B counts for the limit in the thread owning it, but is removed as it's inside A
+        let image = image();
+        for (limit, limits, expected) in [
+            (Some(1), vec![], vec![(11200, 12200)]),
+            (Some(2), vec![], vec![(11200, 12200), (12300, 12400)]),
+            (
+                None,
+                vec![(String::from("bmp"), 1)],
+                vec![(11200, 12200), (15000, 23340)],
+            ),
+        ] {
+            for nb_threads in [1, 2, 3, 4] {
+                let mut corpus = Corpus::new(0);
+                corpus.set_limits(&limits).unwrap();
+                let (dir, artefacts) = carve_with(
+                    &image,
+                    corpus,
+                    nb_threads,
+                    EmbeddedPolicy::Skip,
+                    OversizePolicy::Drop,
+                    limit,
+                    "limits_order",
+                );
+                let offsets: Vec<_> = summary(&dir, &artefacts)
+                    .iter()
+                    .map(|s| (s.1, s.2))
+                    .collect();
+                assert_eq!(offsets, expected);
+            }
+        }
+    }
+}
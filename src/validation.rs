@@ -0,0 +1,24 @@
+// validation of carved artefacts
+// a carved artefact only has the structure of a file: validating it means checking its content
+// can be decoded
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Validation {
+    Valid,       // content is fully decoded
+    Corrupt,     // content is damaged
+    Truncated,   // content ends too early
+    Unsupported, // content uses features the validator can't decode
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Validation::Valid => write!(f, "valid"),
+            Validation::Corrupt => write!(f, "corrupt"),
+            Validation::Truncated => write!(f, "truncated"),
+            Validation::Unsupported => write!(f, "unsupported"),
+        }
+    }
+}